@@ -0,0 +1,805 @@
+use std;
+
+use {ArtKey, ArtConfig, ConfigError};
+use nodes::{ArtNodeBase, MAX_PREFIX_LEN, common_prefix_len};
+
+// Arena backed variant of `ArtTree`. Nodes of each type live in their own pool (a `Vec`), and children refer
+// to each other by `NodeRef`, a 4 byte index tagged with the node type. Compared to `Box` nodes this gives
+// better locality, no per node allocation and a drop which frees a handful of vectors instead of every node.
+//
+// Freed slots are kept on a per pool free list and reused by the next allocation of the same node type.
+//
+// Only the core of `ArtTree` is implemented: insert, get and remove over the 4/16/48/256 node types.
+// Missing compared to `ArtTree`:
+// - `ArtNode8`/`ArtNode32` and span nodes, `with_layout` rejects `intermediate_nodes` and `span_nodes`,
+// - the SIMD `ArtNode16` lookup, the sorted keys are binary searched instead,
+// - `stats`, `memory_usage`, `compact`, `get_many` and `freeze`.
+// `ArtConfig::node_cache` does not apply, the pool free lists already reuse every freed node.
+//
+
+const TAG_BITS: u32 = 3;
+const TAG_MASK: u32 = (1 << TAG_BITS) - 1;
+const MAX_INDEX: usize = 1 << (32 - TAG_BITS);
+
+const TAG_EMPTY: u32 = 0;
+const TAG_LEAF: u32 = 1;
+const TAG_NODE4: u32 = 2;
+const TAG_NODE16: u32 = 3;
+const TAG_NODE48: u32 = 4;
+const TAG_NODE256: u32 = 5;
+
+const EMPTY_CELL: u8 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct NodeRef(u32);
+
+const EMPTY: NodeRef = NodeRef(TAG_EMPTY);
+
+impl NodeRef {
+    #[inline]
+    fn new(tag: u32, idx: usize) -> Self {
+        assert!(idx < MAX_INDEX, "Arena is full");
+        NodeRef((idx as u32) << TAG_BITS | tag)
+    }
+
+    #[inline]
+    fn tag(self) -> u32 {
+        self.0 & TAG_MASK
+    }
+
+    #[inline]
+    fn idx(self) -> usize {
+        (self.0 >> TAG_BITS) as usize
+    }
+
+    #[inline]
+    fn is_inner(self) -> bool {
+        self.tag() >= TAG_NODE4
+    }
+}
+
+struct Pool<T> {
+    slots: Vec<T>,
+    free: Vec<u32>,
+}
+
+impl<T> Pool<T> {
+    fn new() -> Self {
+        Pool { slots: Vec::new(), free: Vec::new() }
+    }
+
+    fn alloc(&mut self, item: T) -> usize {
+        match self.free.pop() {
+            Some(idx) => {
+                self.slots[idx as usize] = item;
+                idx as usize
+            }
+            None => {
+                self.slots.push(item);
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, idx: usize) {
+        self.free.push(idx as u32);
+    }
+}
+
+struct Node4<const P: usize> {
+    n: ArtNodeBase<P>,
+    keys: [u8; 4],
+    children: [NodeRef; 4],
+}
+
+struct Node16<const P: usize> {
+    n: ArtNodeBase<P>,
+    keys: [u8; 16],
+    children: [NodeRef; 16],
+}
+
+struct Node48<const P: usize> {
+    n: ArtNodeBase<P>,
+    keys: [u8; 256],
+    children: [NodeRef; 48],
+}
+
+struct Node256<const P: usize> {
+    n: ArtNodeBase<P>,
+    children: [NodeRef; 256],
+}
+
+struct Nodes<const P: usize> {
+    n4: Pool<Node4<P>>,
+    n16: Pool<Node16<P>>,
+    n48: Pool<Node48<P>>,
+    n256: Pool<Node256<P>>,
+}
+
+pub struct ArenaArtTree<K, V, const P: usize = MAX_PREFIX_LEN> {
+    root: NodeRef,
+    size: usize,
+    leaves: Pool<Option<(K, V)>>,
+    nodes: Nodes<P>,
+    config: ArtConfig,
+}
+
+// Inserts `child` under `byte` into the sorted `keys[..len]`/`children[..len]` of Node4/Node16.
+fn insert_sorted(keys: &mut [u8], children: &mut [NodeRef], len: usize, child: NodeRef, byte: u8) {
+    let idx = keys[..len].iter().position(|&k| k > byte).unwrap_or(len);
+
+    for i in (idx..len).rev() {
+        keys[i + 1] = keys[i];
+        children[i + 1] = children[i];
+    }
+
+    keys[idx] = byte;
+    children[idx] = child;
+}
+
+fn remove_sorted(keys: &mut [u8], children: &mut [NodeRef], len: usize, byte: u8) {
+    let idx = match keys[..len].iter().position(|&k| k == byte) {
+        Some(idx) => idx,
+        None => panic!("Removing child not found"),
+    };
+
+    for i in idx..len - 1 {
+        keys[i] = keys[i + 1];
+        children[i] = children[i + 1];
+    }
+}
+
+impl<const P: usize> Nodes<P> {
+    fn new() -> Self {
+        Nodes {
+            n4: Pool::new(),
+            n16: Pool::new(),
+            n48: Pool::new(),
+            n256: Pool::new(),
+        }
+    }
+
+    fn new_node4(&mut self, n: ArtNodeBase<P>) -> NodeRef {
+        let idx = self.n4.alloc(Node4 { n, keys: [0; 4], children: [EMPTY; 4] });
+        NodeRef::new(TAG_NODE4, idx)
+    }
+
+    fn new_node48(&mut self, n: ArtNodeBase<P>) -> NodeRef {
+        let idx = self.n48.alloc(Node48 { n, keys: [EMPTY_CELL; 256], children: [EMPTY; 48] });
+        NodeRef::new(TAG_NODE48, idx)
+    }
+
+    fn new_node256(&mut self, n: ArtNodeBase<P>) -> NodeRef {
+        let idx = self.n256.alloc(Node256 { n, children: [EMPTY; 256] });
+        NodeRef::new(TAG_NODE256, idx)
+    }
+
+    // Base of the new node after grow/shrink, ie. same prefix and no children.
+    fn moved_base(n: &ArtNodeBase<P>) -> ArtNodeBase<P> {
+        let mut base = ArtNodeBase::new();
+        base.partial = n.partial;
+        base.partial_len = n.partial_len;
+        base
+    }
+
+    #[inline]
+    fn base(&self, node: NodeRef) -> &ArtNodeBase<P> {
+        match node.tag() {
+            TAG_NODE4 => &self.n4.slots[node.idx()].n,
+            TAG_NODE16 => &self.n16.slots[node.idx()].n,
+            TAG_NODE48 => &self.n48.slots[node.idx()].n,
+            TAG_NODE256 => &self.n256.slots[node.idx()].n,
+            _ => panic!("Not an inner node"),
+        }
+    }
+
+    #[inline]
+    fn mut_base(&mut self, node: NodeRef) -> &mut ArtNodeBase<P> {
+        match node.tag() {
+            TAG_NODE4 => &mut self.n4.slots[node.idx()].n,
+            TAG_NODE16 => &mut self.n16.slots[node.idx()].n,
+            TAG_NODE48 => &mut self.n48.slots[node.idx()].n,
+            TAG_NODE256 => &mut self.n256.slots[node.idx()].n,
+            _ => panic!("Not an inner node"),
+        }
+    }
+
+    #[inline]
+    fn find_child(&self, node: NodeRef, byte: u8) -> Option<NodeRef> {
+        let child = match node.tag() {
+            TAG_NODE4 => {
+                let n4 = &self.n4.slots[node.idx()];
+                let len = n4.n.num_children as usize;
+                match n4.keys[..len].iter().position(|&k| k == byte) {
+                    Some(i) => n4.children[i],
+                    None => EMPTY,
+                }
+            }
+            TAG_NODE16 => {
+                let n16 = &self.n16.slots[node.idx()];
+                let len = n16.n.num_children as usize;
+                match n16.keys[..len].binary_search(&byte) {
+                    Ok(i) => n16.children[i],
+                    Err(_) => EMPTY,
+                }
+            }
+            TAG_NODE48 => {
+                let n48 = &self.n48.slots[node.idx()];
+                match n48.keys[byte as usize] {
+                    EMPTY_CELL => EMPTY,
+                    i => n48.children[i as usize - 1],
+                }
+            }
+            TAG_NODE256 => self.n256.slots[node.idx()].children[byte as usize],
+            _ => panic!("Not an inner node"),
+        };
+
+        if child == EMPTY { None } else { Some(child) }
+    }
+
+    // Replaces the existing child under `byte`.
+    fn set_child(&mut self, node: NodeRef, byte: u8, child: NodeRef) {
+        match node.tag() {
+            TAG_NODE4 => {
+                let n4 = &mut self.n4.slots[node.idx()];
+                let len = n4.n.num_children as usize;
+                let i = n4.keys[..len].iter().position(|&k| k == byte).unwrap();
+                n4.children[i] = child;
+            }
+            TAG_NODE16 => {
+                let n16 = &mut self.n16.slots[node.idx()];
+                let len = n16.n.num_children as usize;
+                let i = n16.keys[..len].binary_search(&byte).unwrap();
+                n16.children[i] = child;
+            }
+            TAG_NODE48 => {
+                let n48 = &mut self.n48.slots[node.idx()];
+                let i = n48.keys[byte as usize] as usize - 1;
+                n48.children[i] = child;
+            }
+            TAG_NODE256 => self.n256.slots[node.idx()].children[byte as usize] = child,
+            _ => panic!("Not an inner node"),
+        }
+    }
+
+    fn first_child(&self, node: NodeRef) -> NodeRef {
+        match node.tag() {
+            TAG_NODE4 => self.n4.slots[node.idx()].children[0],
+            TAG_NODE16 => self.n16.slots[node.idx()].children[0],
+            TAG_NODE48 => {
+                let n48 = &self.n48.slots[node.idx()];
+                match n48.keys.iter().find(|&&i| i != EMPTY_CELL) {
+                    Some(&i) => n48.children[i as usize - 1],
+                    None => panic!("No children"),
+                }
+            }
+            TAG_NODE256 => {
+                match self.n256.slots[node.idx()].children.iter().find(|&&c| c != EMPTY) {
+                    Some(&child) => child,
+                    None => panic!("No children"),
+                }
+            }
+            _ => panic!("Not an inner node"),
+        }
+    }
+
+    fn minimum_leaf(&self, mut node: NodeRef) -> NodeRef {
+        while node.is_inner() {
+            node = self.first_child(node);
+        }
+        node
+    }
+
+    // Adds a new child under `byte`. Returns the node, which is a different one if the node had to grow.
+    fn add_child(&mut self, node: NodeRef, byte: u8, child: NodeRef) -> NodeRef {
+        match node.tag() {
+            TAG_NODE4 => {
+                if self.n4.slots[node.idx()].n.num_children < 4 {
+                    let n4 = &mut self.n4.slots[node.idx()];
+                    insert_sorted(&mut n4.keys, &mut n4.children, n4.n.num_children as usize, child, byte);
+                    n4.n.num_children += 1;
+                    return node;
+                }
+
+                let new_node = {
+                    let n4 = &self.n4.slots[node.idx()];
+                    let mut n16 = Node16 { n: Self::moved_base(&n4.n), keys: [0; 16], children: [EMPTY; 16] };
+                    n16.keys[..4].copy_from_slice(&n4.keys);
+                    n16.children[..4].copy_from_slice(&n4.children);
+                    insert_sorted(&mut n16.keys, &mut n16.children, 4, child, byte);
+                    n16.n.num_children = 5;
+                    n16
+                };
+
+                self.n4.release(node.idx());
+                NodeRef::new(TAG_NODE16, self.n16.alloc(new_node))
+            }
+            TAG_NODE16 => {
+                if self.n16.slots[node.idx()].n.num_children < 16 {
+                    let n16 = &mut self.n16.slots[node.idx()];
+                    insert_sorted(&mut n16.keys, &mut n16.children, n16.n.num_children as usize, child, byte);
+                    n16.n.num_children += 1;
+                    return node;
+                }
+
+                let new_node = self.new_node48(Self::moved_base(&self.n16.slots[node.idx()].n));
+                for i in 0..16 {
+                    let (k, c) = {
+                        let n16 = &self.n16.slots[node.idx()];
+                        (n16.keys[i], n16.children[i])
+                    };
+                    self.add_child(new_node, k, c);
+                }
+                self.add_child(new_node, byte, child);
+
+                self.n16.release(node.idx());
+                new_node
+            }
+            TAG_NODE48 => {
+                if self.n48.slots[node.idx()].n.num_children < 48 {
+                    let n48 = &mut self.n48.slots[node.idx()];
+                    n48.children[n48.n.num_children as usize] = child;
+                    n48.n.num_children += 1;
+                    n48.keys[byte as usize] = n48.n.num_children as u8;
+                    return node;
+                }
+
+                let new_node = self.new_node256(Self::moved_base(&self.n48.slots[node.idx()].n));
+                {
+                    let (n48, n256) = (&self.n48.slots[node.idx()], &mut self.n256.slots[new_node.idx()]);
+                    for b in 0..256 {
+                        if n48.keys[b] != EMPTY_CELL {
+                            n256.children[b] = n48.children[n48.keys[b] as usize - 1];
+                        }
+                    }
+                    n256.children[byte as usize] = child;
+                    n256.n.num_children = 49;
+                }
+
+                self.n48.release(node.idx());
+                new_node
+            }
+            TAG_NODE256 => {
+                let n256 = &mut self.n256.slots[node.idx()];
+                n256.children[byte as usize] = child;
+                n256.n.num_children += 1;
+                node
+            }
+            _ => panic!("Not an inner node"),
+        }
+    }
+
+    // Removes the child under `byte`. Returns the node, which is a different one (or the only remaining
+    // child, or EMPTY) if the node had to shrink.
+    fn remove_child(&mut self, node: NodeRef, byte: u8, config: &ArtConfig) -> NodeRef {
+        match node.tag() {
+            TAG_NODE4 => {
+                let remaining = {
+                    let n4 = &mut self.n4.slots[node.idx()];
+                    remove_sorted(&mut n4.keys, &mut n4.children, n4.n.num_children as usize, byte);
+                    n4.n.num_children -= 1;
+                    n4.n.num_children
+                };
+
+                if remaining > 1 {
+                    return node;
+                }
+
+                self.n4.release(node.idx());
+
+                if remaining == 0 {
+                    return EMPTY;
+                }
+
+                // Merge the only child with this node, as in `ArtNode4::shrink`
+                let (n, byte, child) = {
+                    let n4 = &self.n4.slots[node.idx()];
+                    (Self::moved_base(&n4.n), n4.keys[0], n4.children[0])
+                };
+
+                if child.is_inner() {
+                    let base = self.mut_base(child);
+                    let mut partial = [0; P];
+                    let mut len = n.stored_prefix_len();
+                    partial[..len].copy_from_slice(&n.partial[..len]);
+
+                    if len < P {
+                        partial[len] = byte;
+                        len += 1;
+                    }
+
+                    let copy_len = std::cmp::min(P - len, base.stored_prefix_len());
+                    partial[len..len + copy_len].copy_from_slice(&base.partial[..copy_len]);
+
+                    base.partial = partial;
+                    base.partial_len += n.partial_len + 1;
+                }
+
+                child
+            }
+            TAG_NODE16 => {
+                let remaining = {
+                    let n16 = &mut self.n16.slots[node.idx()];
+                    remove_sorted(&mut n16.keys, &mut n16.children, n16.n.num_children as usize, byte);
+                    n16.n.num_children -= 1;
+                    n16.n.num_children
+                };
+
+                if remaining > config.shrink_node16 {
+                    return node;
+                }
+
+                let new_node = {
+                    let n16 = &self.n16.slots[node.idx()];
+                    let len = remaining as usize;
+                    let mut n4 = Node4 { n: Self::moved_base(&n16.n), keys: [0; 4], children: [EMPTY; 4] };
+                    n4.keys[..len].copy_from_slice(&n16.keys[..len]);
+                    n4.children[..len].copy_from_slice(&n16.children[..len]);
+                    n4.n.num_children = remaining;
+                    n4
+                };
+
+                self.n16.release(node.idx());
+                NodeRef::new(TAG_NODE4, self.n4.alloc(new_node))
+            }
+            TAG_NODE48 => {
+                let remaining = {
+                    // Keep children compact, move the last child into the freed slot
+                    let n48 = &mut self.n48.slots[node.idx()];
+                    let slot = n48.keys[byte as usize] as usize - 1;
+                    let last = n48.n.num_children as usize - 1;
+
+                    if slot != last {
+                        n48.children[slot] = n48.children[last];
+                        match n48.keys.iter().position(|&i| i as usize == last + 1) {
+                            Some(moved_byte) => n48.keys[moved_byte] = (slot + 1) as u8,
+                            None => panic!("Moved child not found"),
+                        }
+                    }
+
+                    n48.keys[byte as usize] = EMPTY_CELL;
+                    n48.n.num_children -= 1;
+                    n48.n.num_children
+                };
+
+                if remaining > config.shrink_node48 {
+                    return node;
+                }
+
+                let new_node = {
+                    let n48 = &self.n48.slots[node.idx()];
+                    let mut n16 = Node16 { n: Self::moved_base(&n48.n), keys: [0; 16], children: [EMPTY; 16] };
+                    for b in 0..256 {
+                        if n48.keys[b] != EMPTY_CELL {
+                            let len = n16.n.num_children as usize;
+                            n16.keys[len] = b as u8;
+                            n16.children[len] = n48.children[n48.keys[b] as usize - 1];
+                            n16.n.num_children += 1;
+                        }
+                    }
+                    n16
+                };
+
+                self.n48.release(node.idx());
+                NodeRef::new(TAG_NODE16, self.n16.alloc(new_node))
+            }
+            TAG_NODE256 => {
+                let remaining = {
+                    let n256 = &mut self.n256.slots[node.idx()];
+                    n256.children[byte as usize] = EMPTY;
+                    n256.n.num_children -= 1;
+                    n256.n.num_children
+                };
+
+                if remaining > config.shrink_node256 {
+                    return node;
+                }
+
+                let new_node = self.new_node48(Self::moved_base(&self.n256.slots[node.idx()].n));
+                for b in 0..256 {
+                    let child = self.n256.slots[node.idx()].children[b];
+                    if child != EMPTY {
+                        self.add_child(new_node, b as u8, child);
+                    }
+                }
+
+                self.n256.release(node.idx());
+                new_node
+            }
+            _ => panic!("Not an inner node"),
+        }
+    }
+}
+
+impl<K: ArtKey, V> ArenaArtTree<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: ArtConfig) -> Result<Self, ConfigError> {
+        Self::with_layout(config)
+    }
+}
+
+impl<K: ArtKey, V, const P: usize> Default for ArenaArtTree<K, V, P> {
+    fn default() -> Self {
+        ArenaArtTree {
+            root: EMPTY,
+            size: 0,
+            leaves: Pool::new(),
+            nodes: Nodes::new(),
+            config: ArtConfig::default(),
+        }
+    }
+}
+
+enum Inserted {
+    New,
+    Replaced(NodeRef),
+}
+
+impl<K: ArtKey, V, const P: usize> ArenaArtTree<K, V, P> {
+    // Arena has only the 4/16/48/256 node types. The shrink thresholds of `intermediate_nodes` would shrink
+    // nodes into types too small for their children.
+    pub fn with_layout(config: ArtConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        if config.intermediate_nodes {
+            return Err(ConfigError::IntermediateNodesUnsupported);
+        }
+        if config.span_nodes {
+            return Err(ConfigError::SpanNodesUnsupported);
+        }
+
+        Ok(ArenaArtTree { config, ..Self::default() })
+    }
+
+    pub fn config(&self) -> &ArtConfig {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    #[inline]
+    fn leaf(&self, leaf: NodeRef) -> &(K, V) {
+        match self.leaves.slots[leaf.idx()] {
+            Some(ref leaf) => leaf,
+            None => panic!("Freed leaf"),
+        }
+    }
+
+    fn replace_child(&mut self, parent: Option<(NodeRef, u8)>, child: NodeRef) {
+        match parent {
+            Some((node, byte)) => self.nodes.set_child(node, byte, child),
+            None => self.root = child,
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        let leaf = NodeRef::new(TAG_LEAF, self.leaves.alloc(Some((key, value))));
+
+        let inserted = {
+            let key_bytes = self.leaves.slots[leaf.idx()].as_ref().unwrap().0.bytes();
+            let leaves = &self.leaves;
+            let nodes = &mut self.nodes;
+
+            let leaf_key = |leaf: NodeRef| &leaves.slots[leaf.idx()].as_ref().unwrap().0;
+
+            let mut parent = None;
+            let mut node = self.root;
+            let mut depth = 0;
+
+            loop {
+                if node == EMPTY {
+                    break (parent, leaf, Inserted::New);
+                }
+
+                if !node.is_inner() {
+                    // The key bytes before `depth` matched the full prefixes on the way down
+                    let lkey = leaf_key(node).bytes();
+                    let lcp = depth + common_prefix_len(&lkey[depth..], &key_bytes[depth..]);
+
+                    if lcp == lkey.len() && lcp == key_bytes.len() {
+                        break (parent, leaf, Inserted::Replaced(node));
+                    }
+
+                    assert!(lcp < lkey.len() && lcp < key_bytes.len(), "Keys must not be prefixes of each other");
+
+                    let mut base = ArtNodeBase::new();
+                    let stored = std::cmp::min(lcp - depth, P);
+                    base.partial[..stored].copy_from_slice(&key_bytes[depth..depth + stored]);
+                    base.partial_len = lcp - depth;
+
+                    let new_node = nodes.new_node4(base);
+                    nodes.add_child(new_node, lkey[lcp], node);
+                    nodes.add_child(new_node, key_bytes[lcp], leaf);
+
+                    break (parent, new_node, Inserted::New);
+                }
+
+                // Exact prefix match, the part of the prefix which is not stored is read from a leaf
+                let prefix_match_len = {
+                    let base = nodes.base(node);
+                    let mut lcp = base.compute_prefix_match(&key_bytes, depth);
+
+                    if lcp == base.stored_prefix_len() && base.partial_len > P {
+                        let lkey = leaf_key(nodes.minimum_leaf(node)).bytes();
+                        let max_match = std::cmp::min(base.partial_len, key_bytes.len().saturating_sub(depth));
+                        while lcp < max_match && lkey[depth + lcp] == key_bytes[depth + lcp] {
+                            lcp += 1;
+                        }
+                    }
+                    lcp
+                };
+
+                if prefix_match_len != nodes.base(node).partial_len {
+                    // Split the prefix, new node gets the matching part, old node the rest after the mismatch
+                    let mut base = ArtNodeBase::new();
+                    let stored = std::cmp::min(prefix_match_len, P);
+                    base.partial[..stored].copy_from_slice(&key_bytes[depth..depth + stored]);
+                    base.partial_len = prefix_match_len;
+
+                    let (next_byte_inner, partial) = {
+                        let old = nodes.base(node);
+                        let mut partial = [0; P];
+                        let copy_len = std::cmp::min(old.partial_len - prefix_match_len - 1, P);
+
+                        if old.partial_len <= P {
+                            let src = prefix_match_len + 1;
+                            partial[..copy_len].copy_from_slice(&old.partial[src..src + copy_len]);
+                            (old.partial[prefix_match_len], partial)
+                        } else {
+                            let lkey = leaf_key(nodes.minimum_leaf(node)).bytes();
+                            let src = depth + prefix_match_len + 1;
+                            partial[..copy_len].copy_from_slice(&lkey[src..src + copy_len]);
+                            (lkey[depth + prefix_match_len], partial)
+                        }
+                    };
+
+                    {
+                        let old = nodes.mut_base(node);
+                        old.partial = partial;
+                        old.partial_len -= prefix_match_len + 1;
+                    }
+
+                    let new_node = nodes.new_node4(base);
+                    nodes.add_child(new_node, next_byte_inner, node);
+                    nodes.add_child(new_node, key_bytes[depth + prefix_match_len], leaf);
+
+                    break (parent, new_node, Inserted::New);
+                }
+
+                depth += prefix_match_len;
+                let next_byte = key_bytes[depth];
+
+                match nodes.find_child(node, next_byte) {
+                    Some(child) => {
+                        parent = Some((node, next_byte));
+                        node = child;
+                        depth += 1;
+                    }
+                    None => {
+                        let new_node = nodes.add_child(node, next_byte, leaf);
+                        break (parent, new_node, Inserted::New);
+                    }
+                }
+            }
+        };
+
+        let (parent, child, inserted) = inserted;
+        self.replace_child(parent, child);
+
+        match inserted {
+            Inserted::New => self.size += 1,
+            Inserted::Replaced(old) => {
+                self.leaves.slots[old.idx()] = None;
+                self.leaves.release(old.idx());
+            }
+        }
+    }
+
+    // Same as `ArtTree::skip_prefix`: optimistic, prefix bytes which are not stored are verified by the leaf
+    // comparison, and `checked` follows the depth as long as the prefixes on the way are fully stored.
+    #[inline]
+    fn skip_prefix(base: &ArtNodeBase<P>, key: &[u8], depth: usize, checked: &mut usize) -> Option<usize> {
+        if base.compute_prefix_match(key, depth) != base.stored_prefix_len() {
+            return None;
+        }
+
+        let next_depth = depth + base.partial_len;
+        if *checked == depth && base.partial_len <= P && next_depth < key.len() {
+            *checked = next_depth + 1;
+        }
+
+        if next_depth < key.len() {
+            Some(next_depth)
+        } else {
+            None
+        }
+    }
+
+    // Final leaf check of a search, only the key bytes from `checked` on are compared.
+    #[inline]
+    fn leaf_matches(leaf_key: &K, key: &[u8], checked: usize) -> bool {
+        let leaf_key = leaf_key.bytes();
+        leaf_key.len() == key.len() && common_prefix_len(&leaf_key[checked..], &key[checked..]) == key.len() - checked
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let key_bytes = key.bytes();
+        let mut node = self.root;
+        let mut depth = 0;
+        let mut checked = 0;
+
+        while node.is_inner() {
+            depth = Self::skip_prefix(self.nodes.base(node), &key_bytes, depth, &mut checked)?;
+
+            node = self.nodes.find_child(node, key_bytes[depth])?;
+            depth += 1;
+        }
+
+        if node == EMPTY {
+            return None;
+        }
+
+        let leaf = self.leaf(node);
+        if Self::leaf_matches(&leaf.0, &key_bytes, checked) { Some(&leaf.1) } else { None }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (parent, node, byte, leaf) = {
+            let key_bytes = key.bytes();
+            let mut parent = None;
+            let mut node = self.root;
+            let mut depth = 0;
+            let mut checked = 0;
+
+            if node == EMPTY {
+                return None;
+            }
+
+            if !node.is_inner() {
+                if !Self::leaf_matches(&self.leaf(node).0, &key_bytes, 0) {
+                    return None;
+                }
+                self.root = EMPTY;
+                (None, EMPTY, 0, node)
+            } else {
+                loop {
+                    depth = Self::skip_prefix(self.nodes.base(node), &key_bytes, depth, &mut checked)?;
+
+                    let byte = key_bytes[depth];
+                    let child = self.nodes.find_child(node, byte)?;
+
+                    if child.is_inner() {
+                        parent = Some((node, byte));
+                        node = child;
+                        depth += 1;
+                    } else if Self::leaf_matches(&self.leaf(child).0, &key_bytes, checked) {
+                        break (parent, node, byte, child);
+                    } else {
+                        return None;
+                    }
+                }
+            }
+        };
+
+        if node != EMPTY {
+            let config = self.config;
+            let new_node = self.nodes.remove_child(node, byte, &config);
+            if new_node != node {
+                self.replace_child(parent, new_node);
+            }
+        }
+
+        self.size -= 1;
+        let ret = self.leaves.slots[leaf.idx()].take().map(|(_, value)| value);
+        self.leaves.release(leaf.idx());
+        ret
+    }
+}
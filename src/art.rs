@@ -1,284 +1,876 @@
 
 use std;
-use std::mem;
-
-use {ArtKey, ArtTree};
-use nodes::{ArtNode, ArtNode4, ArtNodeTrait, MAX_PREFIX_LEN};
-
-// TODO: Decide what kind of comparison do we want, ie. PartialEq might not be necessarry.
-// If we decided to compare bytes of the key (which would be correct), we could also use SIMD.
-// On the other hand, user of the lib. could implement smart and quick comparison. 
-//
-// Propably a good way forward for now would be to use PartialEq for now
-//
-impl<'a, K: 'a + ArtKey + std::cmp::PartialEq, V> ArtTree<K, V> {
+use std::borrow::Cow;
+
+use {ArtKey, ArtTree, ArtConfig, ConfigError};
+use nodes::{ArtNode, ArtNodeBase, ArtNodeSpan, ArtNodeTrait, ChildPtr, NodeCache, NodeRef, common_prefix_len};
+
+// Number of searches `ArtTree::get_many` runs interleaved.
+const GET_MANY_GROUP: usize = 16;
+
+// Inner node taken apart by `ArtTree::compact`, rebuilt once all of its children are.
+struct CompactFrame<K, V, const P: usize> {
+    depth: usize,
+    partial_len: usize,
+    // Index of the parent frame (`usize::MAX` for the root) and the key byte under which the node is.
+    parent: usize,
+    byte: u8,
+    children: Vec<(u8, ArtNode<K, V, P>)>,
+}
+
+impl<K: ArtKey, V> ArtTree<K, V> {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: ArtConfig) -> Result<Self, ConfigError> {
+        Self::with_layout(config)
+    }
+}
+
+impl<K: ArtKey, V, const P: usize, const S: usize> Default for ArtTree<K, V, P, S> {
+    fn default() -> Self {
         ArtTree {
-            root: ArtNode::Empty,
+            root: ChildPtr::empty(),
+            size: 0,
+            config: ArtConfig::default(),
+            cache: NodeCache::new(ArtConfig::default().node_cache, S),
+        }
+    }
+}
+
+impl<K: ArtKey, V, const P: usize, const S: usize> ArtTree<K, V, P, S> {
+    // Tree with non-default layout, ie. `ArtTree::<String, u64, 32>::with_layout(config)`.
+    // Default layout trees are created with `new`/`with_config`.
+    pub fn with_layout(config: ArtConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+
+        Ok(ArtTree {
+            root: ChildPtr::empty(),
             size: 0,
+            config,
+            cache: NodeCache::new(config.node_cache, S),
+        })
+    }
+
+    pub fn config(&self) -> &ArtConfig {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    // Length of the match between `key` and the node prefix. Prefix bytes past `P` are read
+    // from a leaf, so the result is exact (pessimistic). Used on insert, where we have to know exactly where
+    // the key diverges from the compressed path.
+    fn prefix_match<N: ArtNodeTrait<K, V, P>>(ptr: &N, key: &[u8], depth: usize) -> usize {
+        Self::prefix_match_with(ptr.base(), || ptr.first_child(), key, depth)
+    }
+
+    // Same as `prefix_match`, for span nodes which do not implement `ArtNodeTrait`.
+    fn prefix_match_with<'b, F>(base: &ArtNodeBase<P>, first_child: F, key: &[u8], depth: usize) -> usize
+        where F: FnOnce() -> &'b ChildPtr<K, V, P>, K: 'b, V: 'b
+    {
+        let stored_match = base.compute_prefix_match(key, depth);
+
+        if stored_match < base.stored_prefix_len() || base.partial_len <= P {
+            return stored_match;
         }
+
+        let leaf_key = first_child().minimum_leaf().key.bytes();
+        let max_match = std::cmp::min(base.partial_len, key.len().saturating_sub(depth));
+
+        stored_match + common_prefix_len(&leaf_key[depth + stored_match..depth + max_match], &key[depth + stored_match..depth + max_match])
     }
 
-    fn break_node<N: ArtNodeTrait<K, V>>(
+    fn break_node<N: ArtNodeTrait<K, V, P>>(
         mut ptr: Box<N>,
         prefix_match_len: usize,
         depth: usize,
         key: K,
         value: V,
-    ) -> ArtNode<K, V> {
-        let mut new_node = Box::new(ArtNode4::new());
+        cache: &mut NodeCache<K, V, P>,
+    ) -> ArtNode<K, V, P> {
+        let mut new_node = cache.node4();
+
+        let next_byte_leaf = {
+            // New node gets the matched part of the prefix, which is the same as the key bytes
+            let key_bytes = key.bytes();
+            let stored = std::cmp::min(prefix_match_len, P);
+            new_node.n.partial[..stored].copy_from_slice(&key_bytes[depth..depth + stored]);
+            new_node.n.partial_len = prefix_match_len;
+
+            key_bytes[depth + prefix_match_len]
+        };
 
-        let next_byte_leaf = key.bytes()[depth + prefix_match_len];
-        let next_byte_inner = ptr.base().partial[prefix_match_len];
+        // Old node keeps the part of the prefix after the mismatching byte
+        let next_byte_inner = if ptr.base().partial_len <= P {
+            let next_byte_inner = ptr.base().partial[prefix_match_len];
 
-        new_node.n.partial_len = prefix_match_len;
+            unsafe {
+                let copy_len = ptr.base().partial_len - prefix_match_len - 1;
+                let src = ptr.base().partial[prefix_match_len+1..ptr.base().partial_len].as_ptr();
+                let dst = ptr.mut_base().partial[..copy_len].as_mut_ptr();
+                std::ptr::copy(src, dst, copy_len);
+            }
 
-        unsafe {
-            std::ptr::copy_nonoverlapping(
-                ptr.base().partial.as_ptr(),
-                new_node.n.partial.as_mut_ptr(),
-                new_node.n.partial.len());
+            next_byte_inner
+        } else {
+            let mut partial = [0; P];
+
+            let next_byte_inner = {
+                let leaf_key = ptr.first_child().minimum_leaf().key.bytes();
+                let copy_len = std::cmp::min(ptr.base().partial_len - prefix_match_len - 1, P);
+                let src = depth + prefix_match_len + 1;
+                partial[..copy_len].copy_from_slice(&leaf_key[src..src + copy_len]);
+
+                leaf_key[depth + prefix_match_len]
+            };
+
+            ptr.mut_base().partial = partial;
+            next_byte_inner
+        };
 
-            let copy_len = ptr.base().partial_len - prefix_match_len - 1;
-            let src = ptr.base().partial[prefix_match_len+1..ptr.base().partial_len].as_ptr();
-            let dst = ptr.mut_base().partial[..copy_len].as_mut_ptr();
-            std::ptr::copy(src, dst, copy_len);
-        }
-                
         ptr.mut_base().partial_len -= prefix_match_len + 1;
 
         new_node.add_child(ptr.to_art_node(), next_byte_inner);
-        new_node.add_child(ArtNode::new_leaf(key, value), next_byte_leaf);
+        new_node.add_child(cache.leaf(key, value), next_byte_leaf);
 
         ArtNode::Inner4(new_node)
     }
 
-    fn insert_inner<N>(mut ptr: Box<N>, depth: usize, key: K, value: V) -> ArtNode<K, V>
-        where N: ArtNodeTrait<K,V>
+    // Called on the node where insert stops descending, ie. the key does not match the node prefix
+    // or there is no child for the next key byte.
+    fn insert_inner<N>(mut ptr: Box<N>, depth: usize, key: K, value: V, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P>
+        where N: ArtNodeTrait<K, V, P>
     {
-        let prefix_match_len = ptr.base().compute_prefix_match(&key, depth);
+        let prefix_match_len = Self::prefix_match(&*ptr, &key.bytes(), depth);
 
         if prefix_match_len != ptr.base().partial_len {
-            Self::break_node(ptr, prefix_match_len, depth, key, value)
+            Self::break_node(ptr, prefix_match_len, depth, key, value, cache)
         } else {
             let next_byte = key.bytes()[depth + prefix_match_len];
 
-            if ptr.has_child(next_byte) {
-                {
-                    let child = ptr.find_child_mut(next_byte);
-                    Self::insert_rec(child, depth + prefix_match_len + 1, key, value);
-                }
-                ptr.to_art_node()
-            } else if ptr.is_full() {
-                ptr.grow_and_add(ArtNode::new_leaf(key, value), next_byte)
+            if ptr.is_full() {
+                ptr.grow_and_add(cache.leaf(key, value), next_byte, config, cache)
             } else {
-                ptr.add_child(ArtNode::new_leaf(key, value), next_byte);
+                ptr.add_child(cache.leaf(key, value), next_byte);
                 ptr.to_art_node()
             }
         }
     }
 
-    fn insert_leaf(lleaf: ArtNode<K,V>, key: K, value: V, depth: usize) -> ArtNode<K,V> {
-        if *lleaf.key() == key {
-            return ArtNode::new_leaf(key, value);
-        }
+    // Insert ends on a leaf at `depth`. The key bytes before `depth` are the same for both keys, as the insert
+    // checked the full prefixes on the way down (see `prefix_match`).
+    // The bool is false if the key was already in the tree, and its value replaced.
+    fn insert_leaf(lleaf: ArtNode<K, V, P>, key: K, value: V, depth: usize, cache: &mut NodeCache<K, V, P>) -> (ArtNode<K, V, P>, bool) {
+        let mut new_node = cache.node4();
+
+        let next_bytes = {
+            let lkey = lleaf.key().bytes();
+            let rkey = key.bytes();
 
-        let mut new_node = Box::new(ArtNode4::new());
+            let lcp = depth + common_prefix_len(&lkey[depth..], &rkey[depth..]);
 
-        let (lnext, rnext) = {
-            let lkey = lleaf.key();
+            if lcp == lkey.len() && lcp == rkey.len() {
+                None
+            } else {
+                assert!(lcp < lkey.len() && lcp < rkey.len(), "Keys must not be prefixes of each other");
 
-            let mut lcp = depth;
-            let max_lcp = std::cmp::min(MAX_PREFIX_LEN, key.bytes().len());
+                let stored = std::cmp::min(lcp - depth, P);
+                new_node.n.partial[..stored].copy_from_slice(&rkey[depth..depth + stored]);
+                new_node.n.partial_len = lcp - depth;
 
-            while lcp < max_lcp && lkey.bytes()[lcp] == key.bytes()[lcp] {
-                lcp += 1;
+                Some((lkey[lcp], rkey[lcp]))
             }
+        };
 
-            if lcp > depth {
-                unsafe {
-                    std::ptr::copy(
-                        key.bytes()[depth..].as_ptr(),
-                        new_node.n.partial.as_mut_ptr(),
-                        lcp - depth
-                    );
-                }
+        let (lnext, rnext) = match next_bytes {
+            Some(next_bytes) => next_bytes,
+            None => {
+                cache.recycle4(new_node);
+                return (cache.leaf(key, value), false);
             }
-
-            new_node.n.partial_len = lcp - depth;
-
-            (lkey.bytes()[lcp], key.bytes()[lcp])
         };
 
-        let rleaf = ArtNode::new_leaf(key, value);
+        let rleaf = cache.leaf(key, value);
 
         new_node.add_child(lleaf, lnext);
         new_node.add_child(rleaf, rnext);
 
-        ArtNode::Inner4(new_node)
+        (ArtNode::Inner4(new_node), true)
     }
 
-    fn insert_rec(root: &mut ArtNode<K, V>, depth: usize, key: K, value: V) {
-        *root = match mem::replace(root, ArtNode::Empty) {
-            ArtNode::Empty => ArtNode::new_leaf(key, value),
+    // Key slot and depth of the child to descend into on insert, or None if the insert ends at this node.
+    #[inline]
+    fn insert_next<N: ArtNodeTrait<K, V, P>>(ptr: &N, key: &[u8], depth: usize) -> Option<(usize, usize)> {
+        let prefix_match_len = Self::prefix_match(ptr, key, depth);
 
-            ArtNode::Inner4(ptr) => Self::insert_inner(ptr, depth, key, value),
+        if prefix_match_len != ptr.base().partial_len {
+            return None;
+        }
 
-            ArtNode::Inner16(ptr) => Self::insert_inner(ptr, depth, key, value),
+        let next_byte = key[depth + prefix_match_len];
+        if ptr.has_child(next_byte) {
+            Some((next_byte as usize, depth + prefix_match_len + 1))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn insert_next_span(ptr: &ArtNodeSpan<K, V, P>, key: &[u8], depth: usize) -> Option<(usize, usize)> {
+        let prefix_match_len = Self::prefix_match_with(&ptr.n, || ptr.first_child(), key, depth);
+
+        if prefix_match_len != ptr.n.partial_len {
+            return None;
+        }
 
-            ArtNode::Inner48(ptr) => Self::insert_inner(ptr, depth, key, value),
+        let next_depth = depth + prefix_match_len;
+        ArtNodeSpan::<K, V, P>::slot(key, next_depth)
+            .and_then(|slot| ptr.find_child(slot).map(|_| (slot, next_depth + 2)))
+    }
+
+    // Insert into an empty slot of the span node. If the key does not match the prefix, the span node is
+    // split and the key is inserted into the resulting `ArtNode256` instead.
+    fn insert_span(mut ptr: Box<ArtNodeSpan<K, V, P>>, depth: usize, key: K, value: V, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let slot = {
+            let key_bytes = key.bytes();
+            let prefix_match_len = Self::prefix_match_with(&ptr.n, || ptr.first_child(), &key_bytes, depth);
+
+            if prefix_match_len == ptr.n.partial_len {
+                match ArtNodeSpan::<K, V, P>::slot(&key_bytes, depth + prefix_match_len) {
+                    Some(slot) => Some(slot),
+                    None => panic!("Keys must not be prefixes of each other"),
+                }
+            } else {
+                None
+            }
+        };
 
-            ArtNode::Inner256(ptr) => Self::insert_inner(ptr, depth, key, value),
+        match slot {
+            Some(slot) => {
+                ptr.add_child(cache.leaf(key, value), slot);
+                ArtNode::Span(ptr)
+            }
+            None => match ptr.split(config, cache) {
+                ArtNode::Inner256(node) => Self::insert_inner(node, depth, key, value, config, cache),
+                _ => panic!("Span node splits into ArtNode256"),
+            },
+        }
+    }
 
-            leaf => Self::insert_leaf(leaf, key, value, depth),
+    // Called after a child of `parent` grew into `ArtNode256`, which may have completed a dense two byte
+    // level below `parent`.
+    fn span_parent(parent: &mut ChildPtr<K, V, P>, cache: &mut NodeCache<K, V, P>) {
+        let dense = match parent.get() {
+            NodeRef::Inner256(ptr) => ArtNodeSpan::can_replace(ptr),
+            _ => false,
         };
+
+        if dense {
+            *parent = ChildPtr::new(match parent.take() {
+                ArtNode::Inner256(ptr) => ArtNode::Span(ArtNodeSpan::from_node256(ptr, cache)),
+                _ => panic!("Span node can only replace ArtNode256"),
+            });
+        }
     }
 
+    // Insert, search and remove walk down the tree in a loop rather than recursively, so that deep trees
+    // (long keys with a lot of branching) cannot overflow the stack.
+    //
     pub fn insert(&mut self, key: K, value: V) {
-        Self::insert_rec(&mut self.root, 0, key, value);
-        self.size += 1;
+        let (node, parent, depth) = {
+            let key_bytes = key.bytes();
+            let mut node = &mut self.root;
+            let mut depth = 0;
+            // Kept only for span nodes, the parent is not touched until `node` is done with
+            let mut parent: *mut ChildPtr<K, V, P> = std::ptr::null_mut();
+
+            loop {
+                let next = match node.get() {
+                    NodeRef::Inner4(ptr) => Self::insert_next(ptr, &key_bytes, depth),
+                    NodeRef::Inner8(ptr) => Self::insert_next(ptr, &key_bytes, depth),
+                    NodeRef::Inner16(ptr) => Self::insert_next(ptr, &key_bytes, depth),
+                    NodeRef::Inner32(ptr) => Self::insert_next(ptr, &key_bytes, depth),
+                    NodeRef::Inner48(ptr) => Self::insert_next(ptr, &key_bytes, depth),
+                    NodeRef::Inner256(ptr) => Self::insert_next(ptr, &key_bytes, depth),
+                    NodeRef::Span(ptr) => Self::insert_next_span(ptr, &key_bytes, depth),
+                    _ => None,
+                };
+
+                match next {
+                    Some((slot, next_depth)) => {
+                        parent = node;
+                        node = unsafe { (*parent).child_mut(slot) };
+                        depth = next_depth;
+                    }
+                    None => break (node, parent, depth),
+                }
+            }
+        };
+
+        let (config, cache) = (&self.config, &mut self.cache);
+        let was_node48 = matches!(node.get(), NodeRef::Inner48(_));
+        let mut added = true;
+
+        *node = ChildPtr::new(match node.take() {
+            ArtNode::Empty => cache.leaf(key, value),
+
+            ArtNode::Inner4(ptr) => Self::insert_inner(ptr, depth, key, value, config, cache),
+
+            ArtNode::Inner8(ptr) => Self::insert_inner(ptr, depth, key, value, config, cache),
+
+            ArtNode::Inner16(ptr) => Self::insert_inner(ptr, depth, key, value, config, cache),
+
+            ArtNode::Inner32(ptr) => Self::insert_inner(ptr, depth, key, value, config, cache),
+
+            ArtNode::Inner48(ptr) => Self::insert_inner(ptr, depth, key, value, config, cache),
+
+            ArtNode::Inner256(ptr) => Self::insert_inner(ptr, depth, key, value, config, cache),
+
+            ArtNode::Span(ptr) => Self::insert_span(ptr, depth, key, value, config, cache),
+
+            leaf => {
+                let (node, new_leaf) = Self::insert_leaf(leaf, key, value, depth, cache);
+                added = new_leaf;
+                node
+            }
+        });
+
+        if config.span_nodes && was_node48 && !parent.is_null() {
+            if let NodeRef::Inner256(_) = node.get() {
+                Self::span_parent(unsafe { &mut *parent }, cache);
+            }
+        }
+
+        if added {
+            self.size += 1;
+        }
     }
 
+    // Position of the next key byte after the node prefix, or None if the key does not match the prefix.
+    // Only the stored part of the prefix is checked (optimistic), the rest is verified by the final leaf comparison.
+    // `checked` is how many leading key bytes are known to match every leaf below. A fully stored prefix
+    // moves it past the prefix and the `branch` key bytes the child is looked up by (1, or 2 for span nodes),
+    // so it keeps up with the depth of the child. It stops growing at the first prefix which is not fully stored.
     #[inline]
-    fn search_inner<N: ArtNodeTrait<K,V>>(ptr: &'a N, key: &K, depth: usize) -> Option<&'a V> {
-        let lcp = ptr.base().compute_prefix_match(key, depth);
-
-        if lcp != ptr.base().partial_len {
+    fn skip_prefix(base: &ArtNodeBase<P>, key: &[u8], depth: usize, branch: usize, checked: &mut usize) -> Option<usize> {
+        if base.compute_prefix_match(key, depth) != base.stored_prefix_len() {
             return None;
         }
 
-        if let Some(ref child) = ptr.find_child(key.bytes()[depth + lcp]) {
-            return Self::search_rec(child, key, depth + lcp + 1);
+        let next_depth = depth + base.partial_len;
+        if *checked == depth && base.partial_len <= P && next_depth + branch <= key.len() {
+            *checked = next_depth + branch;
         }
 
-        None
+        if next_depth < key.len() {
+            Some(next_depth)
+        } else {
+            None
+        }
     }
 
-    fn search_rec(root: &'a ArtNode<K,V>, key: &K, depth: usize) -> Option<&'a V> {
-        match root {
-            &ArtNode::Empty => None,
+    // Final leaf check of a search, only the key bytes from `checked` on are compared (see `skip_prefix`).
+    #[inline]
+    fn leaf_matches(leaf_key: &K, key: &[u8], checked: usize) -> bool {
+        let leaf_key = leaf_key.bytes();
+        leaf_key.len() == key.len() && common_prefix_len(&leaf_key[checked..], &key[checked..]) == key.len() - checked
+    }
 
-            &ArtNode::LeafLarge(ref ptr) => if ptr.0 == *key {
-                Some(&ptr.1)
-            } else {
-                None
-            }
-            
-            &ArtNode::LeafLargeKey(ref key_ptr, ref small_value) => if **key_ptr == *key {
-                Some(small_value.reference())
-            } else {
-                None
-            }
+    #[inline]
+    fn search_inner<'b, N: ArtNodeTrait<K, V, P>>(ptr: &'b N, key: &[u8], depth: usize, checked: &mut usize) -> Option<(&'b ChildPtr<K, V, P>, usize)> {
+        let next_depth = Self::skip_prefix(ptr.base(), key, depth, 1, checked)?;
 
-            &ArtNode::LeafLargeValue(ref small_key, ref value_ptr) => if *small_key.reference() == *key {
-                Some(value_ptr)
-            } else {
-                None
-            }
+        ptr.find_child(key[next_depth]).map(|child| (child, next_depth + 1))
+    }
 
-            &ArtNode::LeafSmall(ref small_key, ref small_value) => if *small_key.reference() == *key {
-                Some(small_value.reference())
+    #[inline]
+    fn search_span<'b>(ptr: &'b ArtNodeSpan<K, V, P>, key: &[u8], depth: usize, checked: &mut usize) -> Option<(&'b ChildPtr<K, V, P>, usize)> {
+        let next_depth = Self::skip_prefix(&ptr.n, key, depth, 2, checked)?;
+
+        ArtNodeSpan::<K, V, P>::slot(key, next_depth)
+            .and_then(|slot| ptr.find_child(slot))
+            .map(|child| (child, next_depth + 2))
+    }
+
+    // One hop of a search: the child to continue with and the depth after it, or the result once the
+    // search has ended.
+    #[inline]
+    fn search_step<'b>(node: &'b ChildPtr<K, V, P>, key: &[u8], depth: usize, checked: &mut usize) -> Result<(&'b ChildPtr<K, V, P>, usize), Option<&'b V>> {
+        let next = match node.get() {
+            NodeRef::Empty => None,
+
+            NodeRef::Leaf(leaf) => return Err(if Self::leaf_matches(&leaf.key, key, *checked) {
+                Some(&leaf.value)
             } else {
                 None
-            }
+            }),
 
-            &ArtNode::Inner4(ref ptr) => Self::search_inner(&**ptr, key, depth),
+            NodeRef::Inner4(ptr) => Self::search_inner(ptr, key, depth, checked),
 
-            &ArtNode::Inner16(ref ptr) => Self::search_inner(&**ptr, key, depth),
+            NodeRef::Inner8(ptr) => Self::search_inner(ptr, key, depth, checked),
 
-            &ArtNode::Inner48(ref ptr) => Self::search_inner(&**ptr, key, depth),
+            NodeRef::Inner16(ptr) => Self::search_inner(ptr, key, depth, checked),
 
-            &ArtNode::Inner256(ref ptr) => Self::search_inner(&**ptr, key, depth),
-        }
+            NodeRef::Inner32(ptr) => Self::search_inner(ptr, key, depth, checked),
+
+            NodeRef::Inner48(ptr) => Self::search_inner(ptr, key, depth, checked),
+
+            NodeRef::Inner256(ptr) => Self::search_inner(ptr, key, depth, checked),
+
+            NodeRef::Span(ptr) => Self::search_span(ptr, key, depth, checked),
+        };
+
+        next.ok_or(None)
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
-        Self::search_rec(&self.root, key, 0)
+        let key_bytes = key.bytes();
+        let mut node = &self.root;
+        let mut depth = 0;
+        let mut checked = 0;
+
+        loop {
+            match Self::search_step(node, &key_bytes, depth, &mut checked) {
+                Ok((child, next_depth)) => {
+                    node = child;
+                    depth = next_depth;
+                }
+                Err(value) => return value,
+            }
+        }
     }
 
-    fn remove_leaf(leaf: ArtNode<K,V>, key: &K) -> Option<V> {
-        if *key == *leaf.key() {
-            Some(leaf.value())
-        } else {
-            None
+    // Entry with the smallest key, in the byte order of the keys.
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        if self.root.is_empty() {
+            return None;
         }
+        let leaf = self.root.minimum_leaf();
+        Some((&leaf.key, &leaf.value))
     }
 
-    fn remove_inner<N>(mut ptr: Box<N>, depth: usize, key: &K)-> (ArtNode<K,V>, Option<V>)
-        where N: ArtNodeTrait<K,V>
-    {
-        let prefix_match_len = ptr.base().compute_prefix_match(key, depth);
-        let next_byte = key.bytes()[depth + prefix_match_len];
+    // Entry with the largest key, in the byte order of the keys.
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        if self.root.is_empty() {
+            return None;
+        }
+        let leaf = self.root.maximum_leaf();
+        Some((&leaf.key, &leaf.value))
+    }
 
-        if prefix_match_len != ptr.base().partial_len || !ptr.has_child(next_byte) {
-            (ptr.to_art_node(), None)
-        } else {
-            let ret = Self::remove_rec(ptr.find_child_mut(next_byte), depth + prefix_match_len + 1, key);
-
-            if let Some(&ArtNode::Empty) = ptr.find_child(next_byte) {
-                // TODO: This is weird API, clean_child is called after the child has already been removed.
-                //       Why does remove_child return should_shrink? 
-                //       Do this for now, but lets focus on this sometimes.
-                //
-                let should_shrink = ptr.clean_child(next_byte);
-
-                if should_shrink {
-                    // TODO: After shrink happens, we should recalculate partial
-                    return (ptr.shrink(), ret);
+    // Looks up `keys` in groups of `GET_MANY_GROUP`. Every round advances each search of the group by one
+    // node and prefetches the next one, so the cache misses of the whole group overlap instead of each
+    // lookup waiting on its own (group prefetching). Pays off once the tree does not fit in the cache.
+    pub fn get_many(&self, keys: &[K]) -> Vec<Option<&V>> {
+        let mut result = Vec::with_capacity(keys.len());
+        let mut key_bytes = Vec::with_capacity(GET_MANY_GROUP);
+
+        for group in keys.chunks(GET_MANY_GROUP) {
+            key_bytes.clear();
+            key_bytes.extend(group.iter().map(|key| key.bytes()));
+
+            let mut nodes = [&self.root; GET_MANY_GROUP];
+            let mut depths = [0; GET_MANY_GROUP];
+            let mut checked = [0; GET_MANY_GROUP];
+            let mut found = [None; GET_MANY_GROUP];
+            let mut done = [false; GET_MANY_GROUP];
+            let mut ready = [false; GET_MANY_GROUP];
+            let mut pending = group.len();
+
+            while pending > 0 {
+                for i in 0..group.len() {
+                    if done[i] {
+                        continue;
+                    }
+
+                    if !ready[i] {
+                        nodes[i].prefetch_child(&key_bytes[i], depths[i]);
+                        ready[i] = true;
+                        continue;
+                    }
+
+                    match Self::search_step(nodes[i], &key_bytes[i], depths[i], &mut checked[i]) {
+                        Ok((child, next_depth)) => {
+                            child.prefetch();
+                            nodes[i] = child;
+                            depths[i] = next_depth;
+                            ready[i] = !child.is_wide();
+                        }
+                        Err(value) => {
+                            found[i] = value;
+                            done[i] = true;
+                            pending -= 1;
+                        }
+                    }
                 }
             }
 
-            (ptr.to_art_node(), ret)
+            result.extend_from_slice(&found[..group.len()]);
         }
+
+        result
     }
 
-    fn remove_rec(root: &mut ArtNode<K,V>, depth: usize, key: &K) -> Option<V> {
-        let (new_root, ret) = match mem::replace(root, ArtNode::Empty) {
-            ArtNode::Empty => (ArtNode::Empty, None),
+    // Cleans up after the child under `byte` has been removed, the node shrinks if it got too small.
+    fn remove_inner<N>(mut ptr: Box<N>, byte: u8, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P>
+        where N: ArtNodeTrait<K, V, P>
+    {
+        // TODO: This is weird API, clean_child is called after the child has already been removed.
+        //       Why does remove_child return should_shrink? 
+        //       Do this for now, but lets focus on this sometimes.
+        //
+        if ptr.clean_child(byte, config) {
+            ptr.shrink(config, cache)
+        } else {
+            ptr.to_art_node()
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let key_bytes = key.bytes();
+        let mut node = &mut self.root;
+        let mut depth = 0;
+        let mut checked = 0;
+
+        // Descend until `node` is the parent of the leaf to remove
+        let slot = loop {
+            let next_depth = match node.get() {
+                NodeRef::Empty => None,
+
+                // Only the root can be a leaf here, deeper leaves are checked from their parent
+                NodeRef::Leaf(leaf) => {
+                    if !Self::leaf_matches(&leaf.key, &key_bytes, 0) {
+                        return None;
+                    }
+                    self.size -= 1;
+                    return Some(node.take().value());
+                }
+
+                NodeRef::Inner4(ptr) => Self::skip_prefix(ptr.base(), &key_bytes, depth, 1, &mut checked),
+
+                NodeRef::Inner8(ptr) => Self::skip_prefix(ptr.base(), &key_bytes, depth, 1, &mut checked),
+
+                NodeRef::Inner16(ptr) => Self::skip_prefix(ptr.base(), &key_bytes, depth, 1, &mut checked),
 
-            ArtNode::Inner4(ptr) => Self::remove_inner(ptr, depth, key),
+                NodeRef::Inner32(ptr) => Self::skip_prefix(ptr.base(), &key_bytes, depth, 1, &mut checked),
 
-            ArtNode::Inner16(ptr) => Self::remove_inner(ptr, depth, key),
+                NodeRef::Inner48(ptr) => Self::skip_prefix(ptr.base(), &key_bytes, depth, 1, &mut checked),
 
-            ArtNode::Inner48(ptr) => Self::remove_inner(ptr, depth, key), 
+                NodeRef::Inner256(ptr) => Self::skip_prefix(ptr.base(), &key_bytes, depth, 1, &mut checked),
 
-            ArtNode::Inner256(ptr) => Self::remove_inner(ptr, depth, key),
+                NodeRef::Span(ptr) => Self::skip_prefix(&ptr.n, &key_bytes, depth, 2, &mut checked),
+            };
 
-            leaf => (ArtNode::Empty, Self::remove_leaf(leaf, key)),
+            let (slot, child_depth) = next_depth.and_then(|next_depth| node.key_slot(&key_bytes, next_depth))?;
+
+            match node.child(slot).map(|child| child.get()) {
+                None => return None,
+                Some(NodeRef::Leaf(leaf)) => if Self::leaf_matches(&leaf.key, &key_bytes, checked) {
+                    break slot;
+                } else {
+                    return None;
+                },
+                Some(_) => {}
+            }
+
+            let parent = node;
+            node = parent.child_mut(slot);
+            depth = child_depth;
         };
 
-        *root = new_root;
-        ret
-    }
+        let value = node.child_mut(slot).take().value();
+        let byte = slot as u8;
+
+        let (config, cache) = (&self.config, &mut self.cache);
+        *node = ChildPtr::new(match node.take() {
+            ArtNode::Inner4(ptr) => Self::remove_inner(ptr, byte, config, cache),
+
+            ArtNode::Inner8(ptr) => Self::remove_inner(ptr, byte, config, cache),
+
+            ArtNode::Inner16(ptr) => Self::remove_inner(ptr, byte, config, cache),
+
+            ArtNode::Inner32(ptr) => Self::remove_inner(ptr, byte, config, cache),
+
+            ArtNode::Inner48(ptr) => Self::remove_inner(ptr, byte, config, cache),
+
+            ArtNode::Inner256(ptr) => Self::remove_inner(ptr, byte, config, cache),
+
+            ArtNode::Span(mut ptr) => if ptr.clean_child(slot, config) {
+                ptr.split(config, cache)
+            } else {
+                ArtNode::Span(ptr)
+            },
+
+            _ => panic!("Leaf parent is not an inner node"),
+        });
 
-    pub fn remove(&mut self, key: &K) -> Option<V> {
         self.size -= 1;
-        Self::remove_rec(&mut self.root, 0, key)
+        Some(value)
+    }
+
+    // Takes the children out of an inner node, they are pushed on `stack` to be compacted first.
+    fn compact_take<N: ArtNodeTrait<K, V, P>>(
+        ptr: &mut N,
+        depth: usize,
+        parent: usize,
+        byte: u8,
+        index: usize,
+        stack: &mut Vec<(ArtNode<K, V, P>, usize, usize, u8)>,
+    ) -> CompactFrame<K, V, P> {
+        let partial_len = ptr.base().partial_len;
+
+        let mut bytes = Vec::new();
+        ptr.for_each_child(|byte, _| bytes.push(byte));
+        for &child_byte in bytes.iter() {
+            stack.push((ptr.find_child_mut(child_byte).take(), depth + partial_len + 1, index, child_byte));
+        }
+
+        CompactFrame {
+            depth,
+            partial_len,
+            parent,
+            byte,
+            children: Vec::with_capacity(bytes.len()),
+        }
+    }
+
+    fn compact_fill<N: ArtNodeTrait<K, V, P>>(mut ptr: Box<N>, children: Vec<(u8, ArtNode<K, V, P>)>) -> Box<N> {
+        for (byte, child) in children {
+            ptr.add_child(child, byte);
+        }
+        ptr
+    }
+
+    // Rebuilds a node from its compacted children, in the smallest node type they fit in. A single child
+    // takes the place of the node (see `ArtNode4::shrink`), and the prefix is read again from a leaf.
+    fn compact_node(frame: CompactFrame<K, V, P>, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let CompactFrame { depth, partial_len, mut children, .. } = frame;
+        let num_children = children.len();
+
+        let (mut node, partial_len) = if num_children == 0 {
+            return ArtNode::Empty;
+        } else if num_children == 1 {
+            let mut child = ChildPtr::new(children.pop().unwrap().1);
+            let partial_len = match child.mut_base() {
+                Some(base) => partial_len + 1 + base.partial_len,
+                None => return child.take(),
+            };
+            (child, partial_len)
+        } else {
+            let node = if num_children <= 4 {
+                ArtNode::Inner4(Self::compact_fill(cache.node4(), children))
+            } else if num_children <= 8 && config.intermediate_nodes {
+                ArtNode::Inner8(Self::compact_fill(cache.node8(), children))
+            } else if num_children <= 16 {
+                ArtNode::Inner16(Self::compact_fill(cache.node16(), children))
+            } else if num_children <= 32 && config.intermediate_nodes {
+                ArtNode::Inner32(Self::compact_fill(cache.node32(), children))
+            } else if num_children <= 48 {
+                ArtNode::Inner48(Self::compact_fill(cache.node48(), children))
+            } else {
+                let ptr = Self::compact_fill(cache.node256(), children);
+                if config.span_nodes && ArtNodeSpan::can_replace(&ptr) {
+                    ArtNode::Span(ArtNodeSpan::from_node256(ptr, cache))
+                } else {
+                    ArtNode::Inner256(ptr)
+                }
+            };
+            (ChildPtr::new(node), partial_len)
+        };
+
+        let stored = std::cmp::min(partial_len, P);
+        let mut partial = [0; P];
+        partial[..stored].copy_from_slice(&node.minimum_leaf().key.bytes()[depth..depth + stored]);
+
+        match node.mut_base() {
+            Some(base) => {
+                base.partial = partial;
+                base.partial_len = partial_len;
+            }
+            None => panic!("Compacted node is not an inner node"),
+        }
+        node.take()
+    }
+
+    // Rebuilds the tree into its optimal shape, which removes do not keep up: every node gets the smallest
+    // node type its children fit in (regardless of the shrink thresholds), nodes with a single child are
+    // merged into it and the prefixes are stored again. Cached nodes are freed.
+    //
+    // The tree is taken apart depth-first and put back together bottom-up, both with explicit stacks.
+    //
+    pub fn compact(&mut self) {
+        let (config, cache) = (&self.config, &mut self.cache);
+        let mut frames: Vec<CompactFrame<K, V, P>> = Vec::new();
+        let mut root = ArtNode::Empty;
+
+        // Every entry has the node, its depth and the index of the parent frame with the key byte.
+        let mut stack = vec![(self.root.take(), 0, usize::MAX, 0)];
+
+        while let Some((node, depth, parent, byte)) = stack.pop() {
+            let index = frames.len();
+            let node = match node {
+                ArtNode::Span(mut ptr) => ptr.split(config, cache),
+                node => node,
+            };
+
+            let frame = match node {
+                ArtNode::Empty => continue,
+                ArtNode::Inner4(mut ptr) => Self::compact_take(&mut *ptr, depth, parent, byte, index, &mut stack),
+                ArtNode::Inner8(mut ptr) => Self::compact_take(&mut *ptr, depth, parent, byte, index, &mut stack),
+                ArtNode::Inner16(mut ptr) => Self::compact_take(&mut *ptr, depth, parent, byte, index, &mut stack),
+                ArtNode::Inner32(mut ptr) => Self::compact_take(&mut *ptr, depth, parent, byte, index, &mut stack),
+                ArtNode::Inner48(mut ptr) => Self::compact_take(&mut *ptr, depth, parent, byte, index, &mut stack),
+                ArtNode::Inner256(mut ptr) => Self::compact_take(&mut *ptr, depth, parent, byte, index, &mut stack),
+                ArtNode::Span(_) => panic!("Span node splits into ArtNode256"),
+                leaf => {
+                    if parent == usize::MAX {
+                        root = leaf;
+                    } else {
+                        frames[parent].children.push((byte, leaf));
+                    }
+                    continue;
+                }
+            };
+            frames.push(frame);
+        }
+
+        // Children come after their parent, so they are all rebuilt by the time the parent is
+        while let Some(frame) = frames.pop() {
+            let (parent, byte) = (frame.parent, frame.byte);
+            let node = Self::compact_node(frame, config, cache);
+
+            match node {
+                ArtNode::Empty => {}
+                node => if parent == usize::MAX {
+                    root = node;
+                } else {
+                    frames[parent].children.push((byte, node));
+                },
+            }
+        }
+
+        self.root = ChildPtr::new(root);
+        self.cache.clear();
+    }
+}
+
+// Nodes are dropped with an explicit stack rather than recursively, same as in `insert`.
+impl<K: ArtKey, V, const P: usize, const S: usize> Drop for ArtTree<K, V, P, S> {
+    fn drop(&mut self) {
+        let mut stack = vec![self.root.take()];
+
+        while let Some(node) = stack.pop() {
+            match node {
+                ArtNode::Inner4(mut ptr) => ptr.take_children(&mut stack),
+                ArtNode::Inner8(mut ptr) => ptr.take_children(&mut stack),
+                ArtNode::Inner16(mut ptr) => ptr.take_children(&mut stack),
+                ArtNode::Inner32(mut ptr) => ptr.take_children(&mut stack),
+                ArtNode::Inner48(mut ptr) => ptr.take_children(&mut stack),
+                ArtNode::Inner256(mut ptr) => ptr.take_children(&mut stack),
+                ArtNode::Span(mut ptr) => ptr.take_children(&mut stack),
+                _ => {}
+            }
+        }
     }
 }
 
 impl ArtKey for u32 {
-    fn bytes(&self) -> &[u8] {
+    fn bytes(&self) -> Cow<'_, [u8]> {
         let ptr = self as *const u32 as *const u8;
-        unsafe { std::slice::from_raw_parts(ptr, 4) }
+        Cow::Borrowed(unsafe { std::slice::from_raw_parts(ptr, 4) })
     }
 }
 
 impl ArtKey for u64 {
-    fn bytes(&self) -> &[u8] {
+    fn bytes(&self) -> Cow<'_, [u8]> {
         let ptr = self as *const u64 as *const u8;
-        unsafe { std::slice::from_raw_parts(ptr, 8) }
+        Cow::Borrowed(unsafe { std::slice::from_raw_parts(ptr, 8) })
     }
 }
 
 impl ArtKey for std::string::String {
-    fn bytes(&self) -> &[u8] {
-        self.as_bytes()
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_bytes())
     }
 }
 
 impl ArtKey for std::vec::Vec<u8> {
-    fn bytes(&self) -> &[u8] {
-        self.as_slice()
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {ArtConfig, ArtKey, ArtTree};
+
+    // Depth of the leaf `key` is found at, and how many key bytes were known to match before comparing it.
+    fn search_leaf<K: ArtKey, V, const P: usize, const S: usize>(t: &ArtTree<K, V, P, S>, key: &K) -> (usize, usize) {
+        let key_bytes = key.bytes();
+        let mut node = &t.root;
+        let mut depth = 0;
+        let mut checked = 0;
+
+        loop {
+            match ArtTree::<K, V, P, S>::search_step(node, &key_bytes, depth, &mut checked) {
+                Ok((child, next_depth)) => {
+                    node = child;
+                    depth = next_depth;
+                }
+                Err(value) => {
+                    assert!(value.is_some());
+                    return (depth, checked);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checked_follows_depth() {
+        // short prefixes, every byte on the way is checked and the leaf has nothing left to compare
+        let mut t = ArtTree::new();
+        for i in 0..512u32 {
+            let key = vec![1, 2, (i >> 6) as u8, 3, (i >> 3 & 7) as u8, (i & 7) as u8];
+            t.insert(key, i);
+        }
+        for i in 0..512u32 {
+            let key = vec![1, 2, (i >> 6) as u8, 3, (i >> 3 & 7) as u8, (i & 7) as u8];
+            assert_eq!(search_leaf(&t, &key), (6, 6));
+        }
+
+        // span nodes consume two key bytes
+        let mut t = ArtTree::with_config(ArtConfig { span_nodes: true, ..ArtConfig::default() }).unwrap();
+        for i in 0..100_000u64 {
+            t.insert(i, i);
+        }
+        assert_eq!(t.stats().span, 1);
+        for i in 0..100_000u64 {
+            let (depth, checked) = search_leaf(&t, &i);
+            assert!(depth >= 2);
+            assert_eq!(checked, depth);
+        }
+
+        // a prefix longer than the stored part is verified only by the leaf, from where it starts
+        let mut t = ArtTree::new();
+        for i in 0..100u8 {
+            let mut key = vec![i % 4];
+            key.extend(b"a long shared prefix/");
+            key.push(i);
+            t.insert(key, i);
+        }
+        for i in 0..100u8 {
+            let mut key = vec![i % 4];
+            key.extend(b"a long shared prefix/");
+            key.push(i);
+            assert_eq!(search_leaf(&t, &key), (key.len(), 1));
+        }
     }
 }
@@ -2,6 +2,8 @@
 #[cfg(test)]
 mod bench {
     use ArtTree;
+    use ArtConfig;
+    use ArenaArtTree;
     use rand;
     use test;
 
@@ -37,6 +39,7 @@ mod bench {
     }
 
     bench_num!(bench_insert_art_u64, u64, ArtTree, N);
+    bench_num!(bench_insert_arena_u64, u64, ArenaArtTree, N);
     bench_num!(bench_insert_btree_u64, u64, BTreeMap, N);
     bench_num!(bench_insert_hmap_u64, u64, HashMap, N);
 
@@ -55,7 +58,7 @@ mod bench {
                     for i in 0..$n {
                         // use Vec instead of Str to avoid utf-8 overhead
                         let mut v = Vec::with_capacity($len);
-                        for ch in rng.gen_ascii_chars().take(20) {
+                        for ch in rng.gen_ascii_chars().take($len) {
                             v.push(ch as u8);
                         }
                         test::black_box(t.insert(v, i));
@@ -98,6 +101,34 @@ mod bench {
     bench_search_seq!(bench_search_btree_seq_u64, BTreeMap, N_SEARCH);
     bench_search_seq!(bench_search_hmap_seq_u64, HashMap, N_SEARCH);
 
+    // Sequential ids are dense in the first two key bytes, which a span node covers with a single hop.
+    #[bench]
+    fn bench_search_art_span_seq_u64(b: &mut Bencher) {
+        let mut t = ArtTree::with_config(ArtConfig { span_nodes: true, ..ArtConfig::default() }).unwrap();
+
+        for i in 0..N_SEARCH {
+            t.insert(i, i);
+        }
+
+        b.iter(|| for i in 0..N_SEARCH {
+            test::black_box(t.get(&i));
+        })
+    }
+
+    #[bench]
+    fn bench_search_frozen_seq_u64(b: &mut Bencher) {
+        let mut t = ArtTree::new();
+
+        for i in 0..N_SEARCH {
+            t.insert(i, i);
+        }
+
+        let t = t.freeze();
+        b.iter(|| for i in 0..N_SEARCH {
+            test::black_box(t.get(&i));
+        })
+    }
+
     macro_rules! bench_search_rnd {
         ($name: ident, $mapty: ident, $n: expr) => {
             #[bench]
@@ -119,6 +150,118 @@ mod bench {
     }
 
     bench_search_rnd!(bench_search_art_rnd_u64, ArtTree, N_SEARCH);
+    bench_search_rnd!(bench_search_arena_rnd_u64, ArenaArtTree, N_SEARCH);
     bench_search_rnd!(bench_search_btree_rnd_u64, BTreeMap, N_SEARCH);
     bench_search_rnd!(bench_search_hmap_rnd_u64, HashMap, N_SEARCH);
+
+    // Same lookups as `bench_search_art_rnd_u64`, issued in batches.
+    #[bench]
+    fn bench_search_art_many_rnd_u64(b: &mut Bencher) {
+        let mut rng = new_rng();
+        let mut t = ArtTree::new();
+
+        for i in 0..N_SEARCH {
+            t.insert(rng.gen::<u64>(), i);
+        }
+
+        let keys: Vec<u64> = (0..10 * N_SEARCH).map(|i| i % N_SEARCH).collect();
+        b.iter(|| for batch in keys.chunks(1024) {
+            test::black_box(t.get_many(batch));
+        })
+    }
+
+    // Trees which do not fit the cache, where batching hides the cache misses.
+    macro_rules! bench_search_big {
+        ($name: ident, $n: expr, $search: expr) => {
+            #[bench]
+            fn $name(b: &mut Bencher) {
+                let mut rng = new_rng();
+                let mut t = ArtTree::new();
+                let mut inserted = Vec::with_capacity($n as usize);
+
+                for i in 0..$n {
+                    let k = rng.gen::<u64>();
+                    t.insert(k, i);
+                    inserted.push(k);
+                }
+
+                // not in insertion order, which is also the order of the leaf allocations
+                let keys: Vec<u64> = (0..$n).map(|_| inserted[rng.gen::<u64>() as usize % inserted.len()]).collect();
+
+                b.iter(|| $search(&t, &keys))
+            }
+        }
+    }
+
+    bench_search_big!(bench_search_art_big_rnd_u64, 10 * N_SEARCH, |t: &ArtTree<u64, u64>, keys: &Vec<u64>| for k in keys.iter() {
+        test::black_box(t.get(k));
+    });
+    bench_search_big!(bench_search_art_many_big_rnd_u64, 10 * N_SEARCH, |t: &ArtTree<u64, u64>, keys: &Vec<u64>| for batch in keys.chunks(1024) {
+        test::black_box(t.get_many(batch));
+    });
+
+    #[bench]
+    fn bench_search_frozen_rnd_u64(b: &mut Bencher) {
+        let mut rng = new_rng();
+        let mut t = ArtTree::new();
+
+        for i in 0..N_SEARCH {
+            t.insert(rng.gen::<u64>(), i);
+        }
+
+        let t = t.freeze();
+        b.iter(|| for i in 0..10 * N_SEARCH {
+            let k = i % N_SEARCH;
+            test::black_box(t.get(&k));
+        })
+    }
+
+    // Keys whose every byte (little endian) takes `fanout` values, so that inner nodes have `fanout` children.
+    // 6 and 20 children fit `ArtNode8`/`ArtNode32` with `ArtConfig::intermediate_nodes`, and `ArtNode16`/`ArtNode48`
+    // without.
+    fn fanout_keys(fanout: u64, n: usize) -> Vec<u64> {
+        (0..n as u64).map(|mut i| {
+            let mut k = 0;
+            for byte in 0..8 {
+                k |= (i % fanout) << (8 * byte);
+                i /= fanout;
+            }
+            k
+        }).collect()
+    }
+
+    macro_rules! bench_fanout {
+        ($insert: ident, $search: ident, $config: expr, $fanout: expr) => {
+            #[bench]
+            fn $insert(b: &mut Bencher) {
+                let keys = fanout_keys($fanout, N);
+
+                b.iter(|| {
+                    let mut t = ArtTree::with_config($config).unwrap();
+                    for &k in keys.iter() {
+                        t.insert(k, k);
+                    }
+                    test::black_box(t)
+                })
+            }
+
+            #[bench]
+            fn $search(b: &mut Bencher) {
+                let keys = fanout_keys($fanout, N);
+                let mut t = ArtTree::with_config($config).unwrap();
+                for &k in keys.iter() {
+                    t.insert(k, k);
+                }
+
+                b.iter(|| for k in keys.iter() {
+                    test::black_box(t.get(k));
+                })
+            }
+        }
+    }
+
+    bench_fanout!(bench_insert_art_fanout6, bench_search_art_fanout6, ArtConfig::default(), 6);
+    bench_fanout!(bench_insert_art_mid_fanout6, bench_search_art_mid_fanout6, ArtConfig::with_intermediate_nodes(), 6);
+    bench_fanout!(bench_insert_art_fanout20, bench_search_art_fanout20, ArtConfig::default(), 20);
+    bench_fanout!(bench_insert_art_mid_fanout20, bench_search_art_mid_fanout20, ArtConfig::with_intermediate_nodes(), 20);
 }
@@ -0,0 +1,125 @@
+use std::{error, fmt};
+
+// Inner nodes grow to the next node type once they are full, but shrink only after they drop to the
+// `shrink_*` number of children. The gap between the two is the hysteresis which keeps a node hovering
+// around a node type boundary from being reallocated on every insert and remove. Lower thresholds mean
+// less reallocation (speed), higher thresholds mean oversized nodes are given back sooner (memory).
+//
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArtConfig {
+    // Adds `ArtNode8` and `ArtNode32` to the node types, so that nodes grow 4 -> 8 -> 16 -> 32 -> 48 -> 256.
+    // Less memory for nodes with 5..8 and 17..32 children, for one more grow (and shrink) on the way up.
+    // Every node type then shrinks into the next smaller one, see `ArtConfig::with_intermediate_nodes`.
+    pub intermediate_nodes: bool,
+    // `ArtNode8` shrinks to `ArtNode4` when it has this many children or fewer. Must be in 2..=4.
+    // Only used with `intermediate_nodes`.
+    pub shrink_node8: u16,
+    // `ArtNode16` shrinks to `ArtNode4` when it has this many children or fewer. Must be in 2..=4.
+    // With `intermediate_nodes` it shrinks to `ArtNode8`, must be in (shrink_node8)..=8.
+    pub shrink_node16: u16,
+    // `ArtNode32` shrinks to `ArtNode16` when it has this many children or fewer. Must be in (shrink_node16)..=16.
+    // Only used with `intermediate_nodes`.
+    pub shrink_node32: u16,
+    // `ArtNode48` shrinks to `ArtNode16` when it has this many children or fewer. Must be in (shrink_node16)..=16.
+    // With `intermediate_nodes` it shrinks to `ArtNode32`, must be in (shrink_node32)..=32.
+    pub shrink_node48: u16,
+    // `ArtNode256` shrinks to `ArtNode48` when it has this many children or fewer. Must be in (shrink_node48)..=48.
+    pub shrink_node256: u16,
+    // Replaces an `ArtNode256` whose children are all `ArtNode256`s (a subtree dense in two key bytes,
+    // ie. sequential integer ids) by a single span node indexed by both bytes, see `ArtNodeSpan`. One node hop
+    // less. A span node always has 65536 child slots (512 KiB), a bit less than the 257 `ArtNode256`s it
+    // replaces, however few children they have. The span node is split back once one of its `ArtNode256`s
+    // would shrink.
+    pub span_nodes: bool,
+    // Number of nodes replaced by grow/shrink which are kept per node type and reused instead of allocating.
+    // 0 disables node reuse.
+    pub node_cache: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    Node8Shrink,
+    Node16Shrink,
+    Node32Shrink,
+    Node48Shrink,
+    Node256Shrink,
+    // Node types which `ArenaArtTree` does not have.
+    IntermediateNodesUnsupported,
+    SpanNodesUnsupported,
+}
+
+impl ArtConfig {
+    // Default thresholds with `intermediate_nodes` enabled.
+    pub fn with_intermediate_nodes() -> Self {
+        ArtConfig {
+            intermediate_nodes: true,
+            shrink_node8: 2,
+            shrink_node16: 5,
+            shrink_node32: 12,
+            shrink_node48: 24,
+            ..ArtConfig::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        // A shrunk node has to fit into the smaller node type, and it must not be below the smaller node's own
+        // threshold, otherwise the shrunk node could end up empty. ArtNode4 with a single child is merged into
+        // the child, so no node may shrink into one.
+        if self.intermediate_nodes {
+            if self.shrink_node8 < 2 || self.shrink_node8 > 4 {
+                return Err(ConfigError::Node8Shrink);
+            }
+            if self.shrink_node16 <= self.shrink_node8 || self.shrink_node16 > 8 {
+                return Err(ConfigError::Node16Shrink);
+            }
+            if self.shrink_node32 <= self.shrink_node16 || self.shrink_node32 > 16 {
+                return Err(ConfigError::Node32Shrink);
+            }
+            if self.shrink_node48 <= self.shrink_node32 || self.shrink_node48 > 32 {
+                return Err(ConfigError::Node48Shrink);
+            }
+        } else {
+            if self.shrink_node16 < 2 || self.shrink_node16 > 4 {
+                return Err(ConfigError::Node16Shrink);
+            }
+            if self.shrink_node48 <= self.shrink_node16 || self.shrink_node48 > 16 {
+                return Err(ConfigError::Node48Shrink);
+            }
+        }
+        if self.shrink_node256 <= self.shrink_node48 || self.shrink_node256 > 48 {
+            return Err(ConfigError::Node256Shrink);
+        }
+        Ok(())
+    }
+}
+
+impl Default for ArtConfig {
+    fn default() -> Self {
+        ArtConfig {
+            intermediate_nodes: false,
+            shrink_node8: 2,
+            shrink_node16: 2,
+            shrink_node32: 12,
+            shrink_node48: 10,
+            shrink_node256: 40,
+            span_nodes: false,
+            node_cache: 8,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Node8Shrink => write!(f, "shrink_node8 must be in 2..=4"),
+            ConfigError::Node16Shrink => write!(f, "shrink_node16 must be in 2..=4, or above shrink_node8 and at most 8 with intermediate nodes"),
+            ConfigError::Node32Shrink => write!(f, "shrink_node32 must be above shrink_node16 and at most 16"),
+            ConfigError::Node48Shrink => write!(f, "shrink_node48 must be above shrink_node16 and at most 16, or above shrink_node32 and at most 32 with intermediate nodes"),
+            ConfigError::Node256Shrink => write!(f, "shrink_node256 must be above shrink_node48 and at most 48"),
+            ConfigError::IntermediateNodesUnsupported => write!(f, "intermediate_nodes is not supported by this tree"),
+            ConfigError::SpanNodesUnsupported => write!(f, "span_nodes is not supported by this tree"),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
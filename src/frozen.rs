@@ -0,0 +1,396 @@
+use std;
+
+use {ArtKey, ArtTree};
+use nodes::{ArtNode, ArtNodeTrait, Leaf};
+
+// Child reference: index of a leaf with the `LEAF` bit set, offset of an inner node in `FrozenArt::nodes`
+// otherwise. `EMPTY` marks the missing children of dense nodes (and the root of an empty tree).
+const LEAF: u32 = 1 << 31;
+const EMPTY: u32 = u32::MAX;
+
+// Up to 16 children the key bytes are stored sorted (padded to 16 bytes for the SIMD lookup), up to 48
+// as a 256 byte index into the node's slots, same as `ArtNode48`. Larger nodes have 256 slots.
+const SPARSE: u32 = 0;
+const INDEXED: u32 = 1;
+const DENSE: u32 = 2;
+
+// Every inner node is a run of words in `FrozenArt::nodes`: the header, then the key bytes (sparse and
+// indexed nodes) and the child references. Keeping them together means one or two cache lines per hop.
+//
+// header[0]: kind | num_children << 8
+// header[1]: prefix length. The whole compressed path is stored, not just `P` bytes.
+// header[2]: offset of the prefix in `FrozenArt::prefixes`
+// header[3..5]: leaf range of the subtree. Leaves are in key order, so every subtree is a range of them.
+const HEADER: usize = 5;
+
+fn node_size(num_children: usize) -> usize {
+    if num_children <= 16 {
+        HEADER + 4 + num_children
+    } else if num_children <= 48 {
+        HEADER + 64 + num_children
+    } else {
+        HEADER + 256
+    }
+}
+
+// Read-only copy of an `ArtTree`, see `ArtTree::freeze`. Inner nodes are laid out in breadth-first
+// order in a single array and refer to their children by `u32` offsets, leaves are stored in key order.
+pub struct FrozenArt<K, V> {
+    root: u32,
+    nodes: Vec<u32>,
+    prefixes: Vec<u8>,
+    leaves: Vec<(K, V)>,
+}
+
+// Iterator over `(key, value)` pairs of a `FrozenArt`, in the byte order of the keys.
+pub struct FrozenIter<'a, K: 'a, V: 'a> {
+    leaves: std::slice::Iter<'a, (K, V)>,
+}
+
+// Inner node in depth-first order, while freezing. Children are `children[first..first + num_children]`.
+struct DfsNode {
+    prefix: usize,
+    prefix_len: usize,
+    first: usize,
+    num_children: usize,
+    leaf_start: u32,
+    leaf_end: u32,
+}
+
+impl<K: ArtKey, V, const P: usize, const S: usize> ArtTree<K, V, P, S> {
+    // Moves the children of `ptr` onto `stack` so that they are popped in key order, and records the node.
+    fn freeze_inner<N: ArtNodeTrait<K, V, P>>(
+        ptr: &mut N,
+        depth: usize,
+        prefixes: &mut Vec<u8>,
+        nodes: &mut Vec<DfsNode>,
+        children: &mut Vec<(u8, u32)>,
+        stack: &mut Vec<(ArtNode<K, V, P>, usize, usize)>,
+    ) -> u32 {
+        let prefix = prefixes.len();
+        let prefix_len = ptr.base().partial_len;
+
+        if prefix_len <= P {
+            prefixes.extend_from_slice(&ptr.base().partial[..prefix_len]);
+        } else {
+            let leaf_key = ptr.first_child().minimum_leaf().key.bytes();
+            prefixes.extend_from_slice(&leaf_key[depth..depth + prefix_len]);
+        }
+
+        let first = children.len();
+        ptr.for_each_child(|byte, _| children.push((byte, EMPTY)));
+        let num_children = children.len() - first;
+
+        for i in (first..first + num_children).rev() {
+            stack.push((ptr.find_child_mut(children[i].0).take(), depth + prefix_len + 1, i));
+        }
+
+        nodes.push(DfsNode {
+            prefix,
+            prefix_len,
+            first,
+            num_children,
+            leaf_start: 0,
+            leaf_end: 0,
+        });
+        (nodes.len() - 1) as u32
+    }
+
+    // Turns the tree into a read-only `FrozenArt`, which is faster to search.
+    pub fn freeze(mut self) -> FrozenArt<K, V> {
+        assert!(self.size < LEAF as usize, "FrozenArt holds less than 2^31 keys");
+
+        let mut prefixes = Vec::new();
+        let mut leaves = Vec::with_capacity(self.size);
+        let mut nodes = Vec::new();
+        let mut children = Vec::new();
+        let mut root = EMPTY;
+
+        // Depth-first, so that leaves come in key order. Every entry has the depth of the node and
+        // the position in `children` of the reference to it (`usize::MAX` for the root).
+        let mut stack = vec![(self.root.take(), 0, usize::MAX)];
+
+        while let Some((node, depth, pos)) = stack.pop() {
+            let reference = match node {
+                ArtNode::Empty => continue,
+                ArtNode::Leaf(leaf) => {
+                    let Leaf { key, value } = leaf.into_inner();
+                    leaves.push((key, value));
+                    (leaves.len() - 1) as u32 | LEAF
+                }
+                ArtNode::Inner4(mut ptr) => Self::freeze_inner(&mut *ptr, depth, &mut prefixes, &mut nodes, &mut children, &mut stack),
+                ArtNode::Inner8(mut ptr) => Self::freeze_inner(&mut *ptr, depth, &mut prefixes, &mut nodes, &mut children, &mut stack),
+                ArtNode::Inner16(mut ptr) => Self::freeze_inner(&mut *ptr, depth, &mut prefixes, &mut nodes, &mut children, &mut stack),
+                ArtNode::Inner32(mut ptr) => Self::freeze_inner(&mut *ptr, depth, &mut prefixes, &mut nodes, &mut children, &mut stack),
+                ArtNode::Inner48(mut ptr) => Self::freeze_inner(&mut *ptr, depth, &mut prefixes, &mut nodes, &mut children, &mut stack),
+                ArtNode::Inner256(mut ptr) => Self::freeze_inner(&mut *ptr, depth, &mut prefixes, &mut nodes, &mut children, &mut stack),
+                ArtNode::Span(mut ptr) => match ptr.split(&self.config, &mut self.cache) {
+                    ArtNode::Inner256(mut ptr) => Self::freeze_inner(&mut *ptr, depth, &mut prefixes, &mut nodes, &mut children, &mut stack),
+                    _ => panic!("Span node splits into ArtNode256"),
+                },
+            };
+
+            if pos == usize::MAX {
+                root = reference;
+            } else {
+                children[pos].1 = reference;
+            }
+        }
+
+        // Children come after their parent in depth-first order, so the leaf ranges can be filled in backwards
+        for i in (0..nodes.len()).rev() {
+            if nodes[i].num_children == 0 {
+                continue;
+            }
+
+            let range = |reference: u32| if reference & LEAF != 0 {
+                ((reference & !LEAF), (reference & !LEAF) + 1)
+            } else {
+                (nodes[reference as usize].leaf_start, nodes[reference as usize].leaf_end)
+            };
+
+            let (leaf_start, _) = range(children[nodes[i].first].1);
+            let (_, leaf_end) = range(children[nodes[i].first + nodes[i].num_children - 1].1);
+            nodes[i].leaf_start = leaf_start;
+            nodes[i].leaf_end = leaf_end;
+        }
+
+        // Lay out inner nodes breadth-first
+        let mut order = Vec::with_capacity(nodes.len());
+        let mut offsets = vec![0; nodes.len()];
+        let mut size = 0;
+        if root & LEAF == 0 {
+            order.push(root as usize);
+        }
+
+        let mut i = 0;
+        while i < order.len() {
+            let node = &nodes[order[i]];
+            offsets[order[i]] = size as u32;
+            size += node_size(node.num_children);
+            for &(_, reference) in children[node.first..node.first + node.num_children].iter() {
+                if reference & LEAF == 0 {
+                    order.push(reference as usize);
+                }
+            }
+            i += 1;
+        }
+        assert!(size < LEAF as usize, "FrozenArt holds less than 2^31 words of inner nodes");
+
+        let offset = |reference: u32| if reference & LEAF != 0 { reference } else { offsets[reference as usize] };
+
+        let mut frozen = FrozenArt {
+            root: if leaves.is_empty() { EMPTY } else { offset(root) },
+            nodes: Vec::with_capacity(size),
+            prefixes,
+            leaves,
+        };
+
+        for &idx in order.iter() {
+            let node = &nodes[idx];
+            let node_children = &children[node.first..node.first + node.num_children];
+
+            let kind = if node.num_children <= 16 { SPARSE } else if node.num_children <= 48 { INDEXED } else { DENSE };
+            frozen.nodes.extend_from_slice(&[
+                kind | (node.num_children as u32) << 8,
+                node.prefix_len as u32,
+                node.prefix as u32,
+                node.leaf_start,
+                node.leaf_end,
+            ]);
+
+            let mut keys = [0u8; 256];
+            let slots = frozen.nodes.len();
+
+            match kind {
+                SPARSE => {
+                    for (i, &(byte, _)) in node_children.iter().enumerate() {
+                        keys[i] = byte;
+                    }
+                    frozen.nodes.extend(keys[..16].chunks(4).map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]])));
+                    frozen.nodes.extend(node_children.iter().map(|&(_, reference)| offset(reference)));
+                }
+                INDEXED => {
+                    for (i, &(byte, _)) in node_children.iter().enumerate() {
+                        keys[byte as usize] = (i + 1) as u8;
+                    }
+                    frozen.nodes.extend(keys.chunks(4).map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]])));
+                    frozen.nodes.extend(node_children.iter().map(|&(_, reference)| offset(reference)));
+                }
+                _ => {
+                    frozen.nodes.resize(slots + 256, EMPTY);
+                    for &(byte, reference) in node_children.iter() {
+                        frozen.nodes[slots + byte as usize] = offset(reference);
+                    }
+                }
+            }
+        }
+
+        frozen
+    }
+}
+
+// Position of `byte` in the (sorted, padded to 16) keys of a sparse node.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+fn find_sparse(keys: &[u8], num_children: u32, byte: u8) -> Option<usize> {
+    use std::arch::x86_64::*;
+
+    let keys = &keys[..16];
+    let bitfield = unsafe {
+        let cmp = _mm_cmpeq_epi8(
+            _mm_set1_epi8(byte as i8),
+            _mm_loadu_si128(keys.as_ptr() as *const __m128i));
+        _mm_movemask_epi8(cmp) as u32 & ((1u32 << num_children) - 1)
+    };
+
+    if bitfield != 0 {
+        Some(bitfield.trailing_zeros() as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+#[inline]
+fn find_sparse(keys: &[u8], num_children: u32, byte: u8) -> Option<usize> {
+    keys[..num_children as usize].iter().position(|&key| key == byte)
+}
+
+impl<K, V> FrozenArt<K, V> {
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    // All entries in the byte order of the keys.
+    pub fn iter(&self) -> FrozenIter<'_, K, V> {
+        FrozenIter { leaves: self.leaves.iter() }
+    }
+
+    // Word `idx` of `nodes`. The offsets in the node words are all written by `freeze`, so lookups skip
+    // the bounds checks.
+    #[inline]
+    fn word(&self, idx: usize) -> u32 {
+        debug_assert!(idx < self.nodes.len());
+        unsafe { *self.nodes.get_unchecked(idx) }
+    }
+
+    // `len` key bytes stored in the node words from `offset` on.
+    #[inline]
+    fn bytes(&self, offset: usize, len: usize) -> &[u8] {
+        debug_assert!(offset + len / 4 <= self.nodes.len());
+        unsafe { std::slice::from_raw_parts(self.nodes.as_ptr().add(offset) as *const u8, len) }
+    }
+
+    #[inline]
+    fn prefix(&self, node: usize) -> &[u8] {
+        let start = self.word(node + 2) as usize;
+        &self.prefixes[start..start + self.word(node + 1) as usize]
+    }
+
+    #[inline]
+    fn find_child(&self, node: usize, byte: u8) -> u32 {
+        let header = self.word(node);
+        let keys = node + HEADER;
+
+        match header & 0xff {
+            SPARSE => match find_sparse(self.bytes(keys, 16), header >> 8, byte) {
+                Some(idx) => self.word(keys + 4 + idx),
+                None => EMPTY,
+            },
+            INDEXED => match self.bytes(keys, 256)[byte as usize] {
+                0 => EMPTY,
+                idx => self.word(keys + 64 + idx as usize - 1),
+            },
+            _ => self.word(keys + byte as usize),
+        }
+    }
+}
+
+impl<K: ArtKey, V> FrozenArt<K, V> {
+    // Prefixes are skipped on the way down, the leaf key bytes are compared at the end, as in `ArtTree::get`.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let key_bytes = key.bytes();
+        let mut child = self.root;
+        let mut depth = 0;
+
+        loop {
+            if child == EMPTY {
+                return None;
+            }
+
+            if child & LEAF != 0 {
+                let (ref leaf_key, ref value) = self.leaves[(child & !LEAF) as usize];
+                return if *leaf_key.bytes() == *key_bytes { Some(value) } else { None };
+            }
+
+            let node = child as usize;
+            depth += self.word(node + 1) as usize;
+
+            if depth >= key_bytes.len() {
+                return None;
+            }
+
+            child = self.find_child(node, key_bytes[depth]);
+            depth += 1;
+        }
+    }
+
+    // Entries whose key bytes start with `prefix`, in the byte order of the keys.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> FrozenIter<'_, K, V> {
+        let mut child = self.root;
+        let mut depth = 0;
+
+        let (start, end) = loop {
+            if child == EMPTY {
+                break (0, 0);
+            }
+
+            if child & LEAF != 0 {
+                let idx = (child & !LEAF) as usize;
+                break if self.leaves[idx].0.bytes().starts_with(prefix) { (idx, idx + 1) } else { (0, 0) };
+            }
+
+            let node = child as usize;
+            let node_prefix = self.prefix(node);
+            let rest = &prefix[depth..];
+            let len = std::cmp::min(rest.len(), node_prefix.len());
+
+            if rest[..len] != node_prefix[..len] {
+                break (0, 0);
+            }
+            if rest.len() <= node_prefix.len() {
+                break (self.word(node + 3) as usize, self.word(node + 4) as usize);
+            }
+
+            child = self.find_child(node, rest[node_prefix.len()]);
+            depth += node_prefix.len() + 1;
+        };
+
+        FrozenIter { leaves: self.leaves[start..end].iter() }
+    }
+}
+
+impl<'a, K, V> Iterator for FrozenIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.leaves.next().map(|(key, value)| (key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.leaves.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for FrozenIter<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.leaves.next_back().map(|(key, value)| (key, value))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for FrozenIter<'a, K, V> {}
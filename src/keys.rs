@@ -0,0 +1,222 @@
+use std;
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use {ArtKey};
+
+// Keys in this module store their bytes in a sortable (big-endian) encoding, so that the byte-wise
+// order the tree sees is the same as the natural order of the wrapped value.
+//
+// Every encoding here is fixed length, which also keeps keys of the same type prefix-free.
+//
+// `char`, `Duration` and `SystemTime` are keys on their own and compute their encoding on every access.
+// The `*Key` wrappers hold the encoded bytes instead, which saves the re-encoding on hot paths.
+
+impl ArtKey for bool {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(if *self { &[1] } else { &[0] })
+    }
+}
+
+// `char` encoded as its big-endian code point.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct CharKey([u8; 4]);
+
+impl CharKey {
+    pub fn new(ch: char) -> Self {
+        CharKey((ch as u32).to_be_bytes())
+    }
+
+    pub fn get(&self) -> char {
+        std::char::from_u32(be_u32(&self.0)).unwrap()
+    }
+}
+
+impl From<char> for CharKey {
+    fn from(ch: char) -> Self {
+        CharKey::new(ch)
+    }
+}
+
+impl ArtKey for CharKey {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+}
+
+impl ArtKey for char {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(CharKey::new(*self).0.to_vec())
+    }
+}
+
+// `Duration` encoded as big-endian seconds followed by big-endian nanoseconds.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct DurationKey([u8; 12]);
+
+impl DurationKey {
+    pub fn new(d: Duration) -> Self {
+        let mut bytes = [0; 12];
+        bytes[..8].copy_from_slice(&d.as_secs().to_be_bytes());
+        bytes[8..].copy_from_slice(&d.subsec_nanos().to_be_bytes());
+        DurationKey(bytes)
+    }
+
+    pub fn get(&self) -> Duration {
+        Duration::new(be_u64(&self.0[..8]), be_u32(&self.0[8..]))
+    }
+}
+
+impl From<Duration> for DurationKey {
+    fn from(d: Duration) -> Self {
+        DurationKey::new(d)
+    }
+}
+
+impl ArtKey for DurationKey {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+}
+
+impl ArtKey for Duration {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(DurationKey::new(*self).0.to_vec())
+    }
+}
+
+// `SystemTime` encoded as signed seconds since `UNIX_EPOCH` (with the sign bit flipped, so that
+// times before the epoch sort first) followed by big-endian nanoseconds.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct TimeKey([u8; 12]);
+
+impl TimeKey {
+    pub fn new(t: SystemTime) -> Self {
+        let (secs, nanos) = match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                let d = e.duration();
+                if d.subsec_nanos() == 0 {
+                    (-(d.as_secs() as i64), 0)
+                } else {
+                    (-(d.as_secs() as i64) - 1, 1_000_000_000 - d.subsec_nanos())
+                }
+            }
+        };
+
+        let mut bytes = [0; 12];
+        bytes[..8].copy_from_slice(&(secs as u64 ^ (1 << 63)).to_be_bytes());
+        bytes[8..].copy_from_slice(&nanos.to_be_bytes());
+        TimeKey(bytes)
+    }
+
+    pub fn get(&self) -> SystemTime {
+        let secs = (be_u64(&self.0[..8]) ^ (1 << 63)) as i64;
+        let nanos = be_u32(&self.0[8..]);
+
+        if secs >= 0 {
+            UNIX_EPOCH + Duration::new(secs as u64, nanos)
+        } else {
+            UNIX_EPOCH - Duration::new((-secs) as u64, 0) + Duration::new(0, nanos)
+        }
+    }
+}
+
+impl From<SystemTime> for TimeKey {
+    fn from(t: SystemTime) -> Self {
+        TimeKey::new(t)
+    }
+}
+
+impl ArtKey for TimeKey {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+}
+
+impl ArtKey for SystemTime {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(TimeKey::new(*self).0.to_vec())
+    }
+}
+
+// 16-byte identifier (UUID, ULID, ...). Bytes are used as given, so ids whose byte order
+// follows their natural order (ie. ULIDs, UUIDv7) iterate chronologically.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Id16(pub [u8; 16]);
+
+impl From<[u8; 16]> for Id16 {
+    fn from(bytes: [u8; 16]) -> Self {
+        Id16(bytes)
+    }
+}
+
+impl From<u128> for Id16 {
+    fn from(id: u128) -> Self {
+        Id16(id.to_be_bytes())
+    }
+}
+
+impl ArtKey for Id16 {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_be_bytes(buf)
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_be_bytes(buf)
+}
+
+// Adapters below change the byte encoding of any other key. Note that the tree expects keys to be prefix-free,
+// so `Reversed` keys have to be suffix-free (ie. start with a sentinel byte) for the reversed bytes to be prefix-free.
+
+// Inverts every byte of the wrapped key, so that ordered traversal yields keys from largest to smallest.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Desc<K>(pub K);
+
+impl<K: ArtKey> ArtKey for Desc<K> {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.bytes().iter().map(|b| !b).collect())
+    }
+}
+
+// ASCII case-insensitive key. Two keys are equal if they are equal after ASCII lowercasing.
+#[derive(Clone, Copy, Debug)]
+pub struct CaseFold<K>(pub K);
+
+impl<K: ArtKey> ArtKey for CaseFold<K> {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        let bytes = self.0.bytes();
+        if bytes.iter().any(|b| b.is_ascii_uppercase()) {
+            Cow::Owned(bytes.to_ascii_lowercase())
+        } else {
+            bytes
+        }
+    }
+}
+
+impl<K: ArtKey> PartialEq for CaseFold<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.bytes().eq_ignore_ascii_case(&other.0.bytes())
+    }
+}
+
+impl<K: ArtKey> Eq for CaseFold<K> {}
+
+// Reverses the bytes of the wrapped key, so that keys sharing a suffix share a prefix in the tree.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Reversed<K>(pub K);
+
+impl<K: ArtKey> ArtKey for Reversed<K> {
+    fn bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.bytes().iter().rev().cloned().collect())
+    }
+}
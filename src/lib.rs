@@ -4,15 +4,40 @@ extern crate rand;
 
 mod nodes;
 mod art;
+mod keys;
+mod config;
+mod arena;
+mod stats;
+mod frozen;
+mod slab;
 mod bench;
 
-use nodes::ArtNode;
+use std::borrow::Cow;
 
+use nodes::{ChildPtr, NodeCache, MAX_PREFIX_LEN, SMALL_STRUCT};
+
+pub use config::{ArtConfig, ConfigError};
+pub use arena::ArenaArtTree;
+pub use stats::{ArtStats, MemoryUsage, HeapSize};
+pub use frozen::{FrozenArt, FrozenIter};
+pub use keys::{CharKey, DurationKey, TimeKey, Id16, Desc, CaseFold, Reversed};
+
+// Keys which already hold their bytes contiguously should return `Cow::Borrowed`. Keys with a computed
+// encoding (big-endian integers, escaped tuples, case folded strings, ...) return `Cow::Owned`, and the
+// encoding is recomputed every time the tree needs to look at the key bytes.
+//
 pub trait ArtKey {
-    fn bytes(&self) -> &[u8];
+    fn bytes(&self) -> Cow<'_, [u8]>;
 }
 
-pub struct ArtTree<K: ArtKey, V> {
-    root: ArtNode<K, V>,
+// `P` is the number of compressed path bytes stored inline in every inner node, longer paths are
+// checked against a leaf key. Leaves whose key and value are both at most `S` bytes are stored inline,
+// in slots of a slab shared by the tree, other leaves are allocated one by one. Larger `P` pays off for
+// keys with long common prefixes (URLs, paths), larger `S` for wider keys (ids).
+//
+pub struct ArtTree<K: ArtKey, V, const P: usize = MAX_PREFIX_LEN, const S: usize = SMALL_STRUCT> {
+    root: ChildPtr<K, V, P>,
     size: usize,
+    config: ArtConfig,
+    cache: NodeCache<K, V, P>,
 }
@@ -1,637 +1,1804 @@
 
 use std;
 use std::{mem, ptr};
+use std::mem::MaybeUninit;
 use std::marker::PhantomData;
 
-use {ArtKey};
+use {ArtKey, ArtConfig};
+use slab::Slab;
 
+// Defaults for the `P` (stored prefix length) and `S` (inline leaf size) parameters of `ArtTree`.
 pub const MAX_PREFIX_LEN: usize = 6;
 pub const SMALL_STRUCT: usize = 8;
 const EMPTY_CELL: u8 = 0;
 
-macro_rules! make_array {
-    ($n:expr, $constructor:expr) => {{
-        let mut items: [_; $n] = std::mem::uninitialized();
-        for place in items.iter_mut() {
-            std::ptr::write(place, $constructor);
-        }
-        items
-    }}
-}
+pub enum ArtNode<K, V, const P: usize> {
+    Empty,
 
-type Small = [u8; SMALL_STRUCT];
+    Inner4(Box<ArtNode4<K, V, P>>),
+    Inner8(Box<ArtNode8<K, V, P>>),
+    Inner16(Box<ArtNode16<K, V, P>>),
+    Inner32(Box<ArtNode32<K, V, P>>),
+    Inner48(Box<ArtNode48<K, V, P>>),
+    Inner256(Box<ArtNode256<K, V, P>>),
+    Span(Box<ArtNodeSpan<K, V, P>>),
 
-pub struct SmallStruct<T> {
-    storage: Small,
-    marker: PhantomData<T>,
+    Leaf(LeafPtr<K, V>),
 }
 
-impl<T> SmallStruct<T> {
-    pub fn new(elem: T) -> Self {
-        unsafe {
-            let mut ret = SmallStruct { storage: mem::uninitialized(), marker: PhantomData };
-            std::ptr::copy_nonoverlapping(
-                &elem as *const T as *const u8,
-                ret.storage.as_mut_ptr(),
-                mem::size_of::<T>());
-            ret
-        }
-    }
+// Nodes are 8 byte aligned, so that `ChildPtr` has the low 3 bits of the pointer for its tag. Leaves are
+// 16 byte aligned, which leaves one more bit for `INLINE_LEAF`.
+#[repr(align(16))]
+pub struct Leaf<K, V> {
+    pub key: K,
+    pub value: V,
+}
 
-    pub fn reference(&self) -> &T {
-        unsafe { &*(self.storage.as_ptr() as *const T) }
-    }
+// Owned leaf, either boxed or in a slot of the leaf slab of the tree (see `NodeCache::leaf`). Slab slots
+// are marked by `INLINE_LEAF`, so that the leaf is given back to the right place on drop.
+//
+pub struct LeafPtr<K, V> {
+    tagged: usize,
+    marker: PhantomData<Leaf<K, V>>,
+}
 
-    pub fn own(self) -> T {
-        unsafe {
-            let mut ret = mem::uninitialized();
-            let dst = &mut ret as *mut T as *mut u8;
-            std::ptr::copy_nonoverlapping(self.storage.as_ptr(), dst, mem::size_of::<T>());
-            ret
-        }
-    }
+// Child slot of an inner node (and the root of the tree), a single tagged pointer. The low 3 bits tell
+// a leaf and the inner node kinds apart, a leaf pointer keeps its `INLINE_LEAF` bit. Null pointer is the
+// empty slot.
+//
+// `ArtNode` is the unpacked (owned) form, `NodeRef` the borrowed one.
+//
+pub struct ChildPtr<K, V, const P: usize> {
+    tagged: usize,
+    marker: PhantomData<ArtNode<K, V, P>>,
 }
 
-pub enum ArtNode<K, V> {
+pub enum NodeRef<'a, K: 'a, V: 'a, const P: usize> {
     Empty,
 
-    Inner4(Box<ArtNode4<K, V>>),
-    Inner16(Box<ArtNode16<K, V>>),
-    Inner48(Box<ArtNode48<K, V>>),
-    Inner256(Box<ArtNode256<K, V>>),
+    Inner4(&'a ArtNode4<K, V, P>),
+    Inner8(&'a ArtNode8<K, V, P>),
+    Inner16(&'a ArtNode16<K, V, P>),
+    Inner32(&'a ArtNode32<K, V, P>),
+    Inner48(&'a ArtNode48<K, V, P>),
+    Inner256(&'a ArtNode256<K, V, P>),
+    Span(&'a ArtNodeSpan<K, V, P>),
 
-    LeafLarge(Box<(K,V)>),
-    LeafLargeKey(Box<K>, SmallStruct<V>),
-    LeafLargeValue(SmallStruct<K>, Box<V>),
-    LeafSmall(SmallStruct<K>, SmallStruct<V>),
+    Leaf(&'a Leaf<K, V>),
 }
 
-pub struct ArtNodeBase {
+const TAG_MASK: usize = 0b111;
+const TAG_NODE4: usize = 0;
+const TAG_LEAF: usize = 1;
+const TAG_NODE8: usize = 2;
+const TAG_NODE16: usize = 3;
+const TAG_NODE32: usize = 4;
+const TAG_NODE48: usize = 5;
+const TAG_NODE256: usize = 6;
+const TAG_SPAN: usize = 7;
+const INLINE_LEAF: usize = 0b1000;
+
+// `partial_len` is the full length of the compressed path, but only the first `P` bytes
+// of it are stored in `partial`. The rest has to be read from (any) leaf below the node.
+//
+pub struct ArtNodeBase<const P: usize> {
     pub num_children: u16,
-    pub partial: [u8; MAX_PREFIX_LEN],
+    pub partial: [u8; P],
     pub partial_len: usize,
 }
 
-pub struct ArtNode4<K, V> {
-    pub n: ArtNodeBase,
-    pub keys: mem::ManuallyDrop<[u8; 4]>,
-    pub children: mem::ManuallyDrop<[ArtNode<K, V>; 4]>,
+// Only `children[..num_children]` are initialized in all nodes but `ArtNode256`, the rest of the
+// slots are uninitialized memory. Nodes drop the initialized children themselves.
+//
+#[repr(align(8))]
+pub struct ArtNode4<K, V, const P: usize> {
+    pub n: ArtNodeBase<P>,
+    pub keys: [u8; 4],
+    pub children: [MaybeUninit<ChildPtr<K, V, P>>; 4],
+}
+
+// `ArtNode8` and `ArtNode32` are only used with `ArtConfig::intermediate_nodes`, they sit between
+// 4/16 and 16/48 so that fewer nodes are oversized right after growing.
+//
+#[repr(align(8))]
+pub struct ArtNode8<K, V, const P: usize> {
+    pub n: ArtNodeBase<P>,
+    pub keys: [u8; 8],
+    pub children: [MaybeUninit<ChildPtr<K, V, P>>; 8],
+}
+
+#[repr(align(8))]
+pub struct ArtNode16<K, V, const P: usize> {
+    pub n: ArtNodeBase<P>,
+    pub keys: [u8; 16],
+    pub children: [MaybeUninit<ChildPtr<K, V, P>>; 16],
 }
 
-pub struct ArtNode16<K, V> {
-    pub n: ArtNodeBase,
-    pub keys: mem::ManuallyDrop<[u8; 16]>,
-    pub children: mem::ManuallyDrop<[ArtNode<K, V>; 16]>,
+#[repr(align(8))]
+pub struct ArtNode32<K, V, const P: usize> {
+    pub n: ArtNodeBase<P>,
+    pub keys: [u8; 32],
+    pub children: [MaybeUninit<ChildPtr<K, V, P>>; 32],
 }
 
-pub struct ArtNode48<K, V> {
-    pub n: ArtNodeBase,
+// Key bytes which have a child in `ArtNode48`/`ArtNode256`, so that the first, last, next, previous or every
+// child is found with a few bit scans instead of walking all 256 slots.
+//
+#[derive(Clone, Copy)]
+pub struct ChildBitmap {
+    words: [u64; 4],
+}
+
+// Cursors are the bytes last returned from either end.
+pub struct ChildBitmapIter {
+    bitmap: ChildBitmap,
+    front: Option<u8>,
+    back: Option<u8>,
+}
+
+#[repr(align(8))]
+pub struct ArtNode48<K, V, const P: usize> {
+    pub n: ArtNodeBase<P>,
+    pub occupied: ChildBitmap,
     pub keys: [u8; 256],
-    pub children: mem::ManuallyDrop<[ArtNode<K, V>; 48]>,
+    pub children: [MaybeUninit<ChildPtr<K, V, P>>; 48],
+}
+
+#[repr(align(8))]
+pub struct ArtNode256<K, V, const P: usize> {
+    pub n: ArtNodeBase<P>,
+    pub occupied: ChildBitmap,
+    pub children: [ChildPtr<K, V, P>; 256],
+}
+
+// Inner node branching on two key bytes at once, only used with `ArtConfig::span_nodes`. Child slot is
+// the 16 bit big-endian index of the two bytes. It replaces an `ArtNode256` whose children are all
+// `ArtNode256`s without prefix, which saves a node hop on every lookup. The 65536 child slots are 512 KiB
+// no matter how many of them are used, a bit less than the 257 `ArtNode256`s replaced.
+//
+// `counts[hi]` is the number of children whose first byte is `hi`, `n.num_children` is not used.
+// All-zero memory is a valid empty span node.
+//
+#[repr(align(8))]
+pub struct ArtNodeSpan<K, V, const P: usize> {
+    pub n: ArtNodeBase<P>,
+    pub counts: [u16; 256],
+    pub children: [ChildPtr<K, V, P>; 65536],
 }
 
-pub struct ArtNode256<K, V> {
-    pub n: ArtNodeBase,
-    pub children: [ArtNode<K, V>; 256],
+// Nodes replaced by `grow_and_add`/`shrink` are kept here, up to `cap` per node type, and handed out
+// again instead of allocating a new node. Helps workloads whose nodes hover around a node type boundary.
+// Cached nodes have no children.
+//
+// Also allocates the leaves, small ones from the leaf slab (see `S` of `ArtTree`).
+//
+pub struct NodeCache<K, V, const P: usize> {
+    cap: usize,
+    leaves: Option<Slab<Leaf<K, V>>>,
+    nodes4: Vec<Box<ArtNode4<K, V, P>>>,
+    nodes8: Vec<Box<ArtNode8<K, V, P>>>,
+    nodes16: Vec<Box<ArtNode16<K, V, P>>>,
+    nodes32: Vec<Box<ArtNode32<K, V, P>>>,
+    nodes48: Vec<Box<ArtNode48<K, V, P>>>,
+    nodes256: Vec<Box<ArtNode256<K, V, P>>>,
+}
+
+impl<K, V, const P: usize> NodeCache<K, V, P> {
+    // Leaves whose key and value both have at most `leaf_size` bytes go to the leaf slab.
+    pub fn new(cap: usize, leaf_size: usize) -> Self {
+        let inline = mem::size_of::<K>() <= leaf_size && mem::size_of::<V>() <= leaf_size;
+
+        NodeCache {
+            cap,
+            leaves: if inline { Some(Slab::new()) } else { None },
+            nodes4: Vec::new(),
+            nodes8: Vec::new(),
+            nodes16: Vec::new(),
+            nodes32: Vec::new(),
+            nodes48: Vec::new(),
+            nodes256: Vec::new(),
+        }
+    }
+
+    // Frees the cached nodes, leaves still in the tree keep their slab.
+    pub fn clear(&mut self) {
+        self.nodes4 = Vec::new();
+        self.nodes8 = Vec::new();
+        self.nodes16 = Vec::new();
+        self.nodes32 = Vec::new();
+        self.nodes48 = Vec::new();
+        self.nodes256 = Vec::new();
+    }
+
+    #[inline]
+    pub fn leaf(&mut self, key: K, value: V) -> ArtNode<K, V, P> {
+        match self.leaves {
+            Some(ref mut slab) => ArtNode::Leaf(LeafPtr::inline(slab.alloc(Leaf { key, value }))),
+            None => ArtNode::new_leaf(key, value),
+        }
+    }
+
+    pub fn node4(&mut self) -> Box<ArtNode4<K, V, P>> {
+        self.nodes4.pop().unwrap_or_else(|| Box::new(ArtNode4::new()))
+    }
+
+    pub fn node8(&mut self) -> Box<ArtNode8<K, V, P>> {
+        self.nodes8.pop().unwrap_or_else(|| Box::new(ArtNode8::new()))
+    }
+
+    pub fn node16(&mut self) -> Box<ArtNode16<K, V, P>> {
+        self.nodes16.pop().unwrap_or_else(|| Box::new(ArtNode16::new()))
+    }
+
+    pub fn node32(&mut self) -> Box<ArtNode32<K, V, P>> {
+        self.nodes32.pop().unwrap_or_else(|| Box::new(ArtNode32::new()))
+    }
+
+    pub fn node48(&mut self) -> Box<ArtNode48<K, V, P>> {
+        self.nodes48.pop().unwrap_or_else(|| Box::new(ArtNode48::new()))
+    }
+
+    pub fn node256(&mut self) -> Box<ArtNode256<K, V, P>> {
+        self.nodes256.pop().unwrap_or_else(|| Box::new(ArtNode256::new()))
+    }
+
+    // Bytes held by the cached nodes.
+    pub fn memory_usage(&self) -> usize {
+        self.nodes4.len() * mem::size_of::<ArtNode4<K, V, P>>() +
+            self.nodes8.len() * mem::size_of::<ArtNode8<K, V, P>>() +
+            self.nodes16.len() * mem::size_of::<ArtNode16<K, V, P>>() +
+            self.nodes32.len() * mem::size_of::<ArtNode32<K, V, P>>() +
+            self.nodes48.len() * mem::size_of::<ArtNode48<K, V, P>>() +
+            self.nodes256.len() * mem::size_of::<ArtNode256<K, V, P>>()
+    }
+
+    // Leaves of the tree are all inline or all boxed, see `new`.
+    pub fn has_leaf_slab(&self) -> bool {
+        self.leaves.is_some()
+    }
+
+    // Bytes of the leaf slab which are not taken by leaves.
+    pub fn leaf_slab_overhead(&self) -> usize {
+        self.leaves.as_ref().map_or(0, |slab| slab.overhead())
+    }
+
+    // Recycled nodes must already be emptied, only the bookkeeping is reset here.
+    pub fn recycle4(&mut self, mut node: Box<ArtNode4<K, V, P>>) {
+        if self.nodes4.len() < self.cap {
+            node.n = ArtNodeBase::new();
+            self.nodes4.push(node);
+        }
+    }
+
+    pub fn recycle8(&mut self, mut node: Box<ArtNode8<K, V, P>>) {
+        if self.nodes8.len() < self.cap {
+            node.n = ArtNodeBase::new();
+            node.keys = [0; 8];
+            self.nodes8.push(node);
+        }
+    }
+
+    pub fn recycle16(&mut self, mut node: Box<ArtNode16<K, V, P>>) {
+        if self.nodes16.len() < self.cap {
+            node.n = ArtNodeBase::new();
+            node.keys = [0; 16];
+            self.nodes16.push(node);
+        }
+    }
+
+    pub fn recycle32(&mut self, mut node: Box<ArtNode32<K, V, P>>) {
+        if self.nodes32.len() < self.cap {
+            node.n = ArtNodeBase::new();
+            node.keys = [0; 32];
+            self.nodes32.push(node);
+        }
+    }
+
+    pub fn recycle48(&mut self, mut node: Box<ArtNode48<K, V, P>>) {
+        if self.nodes48.len() < self.cap {
+            node.n = ArtNodeBase::new();
+            node.occupied = ChildBitmap::new();
+            node.keys = [EMPTY_CELL; 256];
+            self.nodes48.push(node);
+        }
+    }
+
+    pub fn recycle256(&mut self, mut node: Box<ArtNode256<K, V, P>>) {
+        if self.nodes256.len() < self.cap {
+            node.n = ArtNodeBase::new();
+            node.occupied = ChildBitmap::new();
+            self.nodes256.push(node);
+        }
+    }
 }
 
-pub trait ArtNodeTrait<K, V> {
-    fn add_child(&mut self, node: ArtNode<K, V>, byte: u8);
+pub trait ArtNodeTrait<K, V, const P: usize> {
+    fn add_child(&mut self, node: ArtNode<K, V, P>, byte: u8);
 
     // After the child has been set to Empty, it can(has) to be cleaned up, so that bookeeping vars are updated.
-    // @return: returns `should_shrink` boolean.
+    // @return: returns `should_shrink` boolean, shrink thresholds are taken from `config`.
     //
     // TODO: this API is stupid and should be refactored. This function should return ().
     //
-    // TODO: we have to empirically decide on the `when to shrink` constants. So far they are hardcoded
-    // to `random` numbers. We have to be careful here and make a smart tradeoff between memory consumption and speed.
-    // If we want to 
-    // 
-    fn clean_child(&mut self, byte: u8) -> bool;
+    fn clean_child(&mut self, byte: u8, config: &ArtConfig) -> bool;
 
     #[inline]
     fn is_full(&self) -> bool;
 
-    fn grow_and_add(self, leaf: ArtNode<K, V>, byte: u8) -> ArtNode<K, V>;
+    // Replaced node is given back to `cache`, and the new node is taken from it if possible. The node type
+    // grown (or shrunk) into depends on `config.intermediate_nodes`.
+    fn grow_and_add(self: Box<Self>, leaf: ArtNode<K, V, P>, byte: u8, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P>;
 
-    // TODO: recalculation of partial after shrink is not necessary, but could be useful. Not clear at this point
-    //
-    fn shrink(self) -> ArtNode<K,V>;
+    // Counterpart of `grow_and_add`, called once `clean_child` returns true.
+    fn shrink(self: Box<Self>, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P>;
+
+    // Moves the compressed path and all children into the empty node `to`, which has to have room for them.
+    fn drain_into<N: ArtNodeTrait<K, V, P>>(&mut self, to: &mut N);
 
     #[inline]
-    fn mut_base(&mut self) -> &mut ArtNodeBase;
+    fn mut_base(&mut self) -> &mut ArtNodeBase<P>;
 
     #[inline]
-    fn base(&self) -> &ArtNodeBase;
+    fn base(&self) -> &ArtNodeBase<P>;
 
     #[inline]
-    fn find_child_mut(&mut self, byte: u8) -> &mut ArtNode<K, V>;
+    fn find_child_mut(&mut self, byte: u8) -> &mut ChildPtr<K, V, P>;
 
     #[inline]
-    fn find_child(&self, byte: u8) -> Option<&ArtNode<K, V>>;
+    fn find_child(&self, byte: u8) -> Option<&ChildPtr<K, V, P>>;
 
     #[inline]
     fn has_child(&self, byte: u8) -> bool;
 
+    // Child with the smallest key byte. Node has to have at least one child.
+    fn first_child(&self) -> &ChildPtr<K, V, P>;
+
+    // Child with the largest key byte. Node has to have at least one child.
+    fn last_child(&self) -> &ChildPtr<K, V, P>;
+
     #[inline]
-    fn to_art_node(self: Box<Self>) -> ArtNode<K, V>;
+    fn to_art_node(self: Box<Self>) -> ArtNode<K, V, P>;
+
+    // Moves all children out into `out`, the node is left without children.
+    fn take_children(&mut self, out: &mut Vec<ArtNode<K, V, P>>);
+
+    // Calls `f` for every child, in ascending order of the key byte.
+    fn for_each_child<'a, F: FnMut(u8, &'a ChildPtr<K, V, P>)>(&'a self, f: F) where K: 'a, V: 'a;
 }
 
-impl<K: ArtKey, V> ArtNode<K, V> {
+impl<K, V, const P: usize> ArtNode<K, V, P> {
     #[inline]
     pub fn key(&self) -> &K {
         match self {
-            &ArtNode::LeafLarge(ref ptr) => &ptr.0,
-            &ArtNode::LeafLargeKey(ref key_ptr, _) => &*key_ptr,
-            &ArtNode::LeafLargeValue(ref key_small, _) => key_small.reference(),
-            &ArtNode::LeafSmall(ref key_small, _) => key_small.reference(),
+            &ArtNode::Leaf(ref leaf) => &leaf.key,
             _ => panic!("Does not contain key"),
         }
     }
 
     pub fn value(self) -> V {
         match self {
-            ArtNode::LeafLarge(ptr) => ptr.1,
-            ArtNode::LeafLargeKey(_, value_small) => value_small.own(),
-            ArtNode::LeafLargeValue(_, value_ptr) => *value_ptr,
-            ArtNode::LeafSmall(_, value_small) => value_small.own(),
+            ArtNode::Leaf(leaf) => leaf.into_inner().value,
             _ => panic!("Does not contain value"),
         }
     }
 
+    // Boxed leaf, `NodeCache::leaf` decides between boxed and inline.
     #[inline]
-    pub fn new_leaf(key: K, value: V) -> ArtNode<K,V> {
-        if mem::size_of::<K>() > SMALL_STRUCT {
-            if mem::size_of::<V>() > SMALL_STRUCT {
-                ArtNode::LeafLarge(Box::new((key,value)))
-            } else {
-                ArtNode::LeafLargeKey(Box::new(key), SmallStruct::new(value))
+    pub fn new_leaf(key: K, value: V) -> ArtNode<K, V, P> {
+        ArtNode::Leaf(LeafPtr::boxed(Box::new(Leaf { key, value })))
+    }
+}
+
+impl<K, V> LeafPtr<K, V> {
+    #[inline]
+    fn boxed(leaf: Box<Leaf<K, V>>) -> Self {
+        LeafPtr { tagged: Box::into_raw(leaf) as usize, marker: PhantomData }
+    }
+
+    #[inline]
+    fn inline(slot: *mut Leaf<K, V>) -> Self {
+        LeafPtr { tagged: slot as usize | INLINE_LEAF, marker: PhantomData }
+    }
+
+    #[inline]
+    fn as_ptr(&self) -> *mut Leaf<K, V> {
+        (self.tagged & !INLINE_LEAF) as *mut Leaf<K, V>
+    }
+
+    #[inline]
+    fn into_raw(self) -> usize {
+        let tagged = self.tagged;
+        mem::forget(self);
+        tagged
+    }
+
+    #[inline]
+    unsafe fn from_raw(tagged: usize) -> Self {
+        LeafPtr { tagged, marker: PhantomData }
+    }
+
+    // Frees the leaf memory, the leaf has to be moved out or dropped already.
+    #[inline]
+    unsafe fn dealloc(&self) {
+        if self.tagged & INLINE_LEAF != 0 {
+            Slab::free(self.as_ptr());
+        } else {
+            drop(Box::from_raw(self.as_ptr() as *mut MaybeUninit<Leaf<K, V>>));
+        }
+    }
+
+    pub fn into_inner(self) -> Leaf<K, V> {
+        unsafe {
+            let leaf = ptr::read(self.as_ptr());
+            self.dealloc();
+            mem::forget(self);
+            leaf
+        }
+    }
+}
+
+impl<K, V> std::ops::Deref for LeafPtr<K, V> {
+    type Target = Leaf<K, V>;
+
+    #[inline]
+    fn deref(&self) -> &Leaf<K, V> {
+        unsafe { &*self.as_ptr() }
+    }
+}
+
+impl<K, V> Drop for LeafPtr<K, V> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.as_ptr());
+            self.dealloc();
+        }
+    }
+}
+
+impl<K, V, const P: usize> ChildPtr<K, V, P> {
+    #[inline]
+    pub fn empty() -> Self {
+        ChildPtr { tagged: 0, marker: PhantomData }
+    }
+
+    #[inline]
+    pub fn new(node: ArtNode<K, V, P>) -> Self {
+        let tagged = match node {
+            ArtNode::Empty => 0,
+            ArtNode::Inner4(ptr) => Box::into_raw(ptr) as usize | TAG_NODE4,
+            ArtNode::Inner8(ptr) => Box::into_raw(ptr) as usize | TAG_NODE8,
+            ArtNode::Inner16(ptr) => Box::into_raw(ptr) as usize | TAG_NODE16,
+            ArtNode::Inner32(ptr) => Box::into_raw(ptr) as usize | TAG_NODE32,
+            ArtNode::Inner48(ptr) => Box::into_raw(ptr) as usize | TAG_NODE48,
+            ArtNode::Inner256(ptr) => Box::into_raw(ptr) as usize | TAG_NODE256,
+            ArtNode::Span(ptr) => Box::into_raw(ptr) as usize | TAG_SPAN,
+            ArtNode::Leaf(leaf) => leaf.into_raw() | TAG_LEAF,
+        };
+        ChildPtr { tagged, marker: PhantomData }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.tagged == 0
+    }
+
+    // Moves the child out, leaving the slot empty.
+    #[inline]
+    pub fn take(&mut self) -> ArtNode<K, V, P> {
+        let tagged = mem::replace(&mut self.tagged, 0);
+        if tagged == 0 {
+            return ArtNode::Empty;
+        }
+
+        let ptr = tagged & !TAG_MASK;
+        unsafe {
+            match tagged & TAG_MASK {
+                TAG_NODE4 => ArtNode::Inner4(Box::from_raw(ptr as *mut _)),
+                TAG_NODE8 => ArtNode::Inner8(Box::from_raw(ptr as *mut _)),
+                TAG_NODE16 => ArtNode::Inner16(Box::from_raw(ptr as *mut _)),
+                TAG_NODE32 => ArtNode::Inner32(Box::from_raw(ptr as *mut _)),
+                TAG_NODE48 => ArtNode::Inner48(Box::from_raw(ptr as *mut _)),
+                TAG_NODE256 => ArtNode::Inner256(Box::from_raw(ptr as *mut _)),
+                TAG_SPAN => ArtNode::Span(Box::from_raw(ptr as *mut _)),
+                TAG_LEAF => ArtNode::Leaf(LeafPtr::from_raw(ptr)),
+                _ => panic!("Invalid child tag"),
+            }
+        }
+    }
+
+    #[inline]
+    pub fn get(&self) -> NodeRef<'_, K, V, P> {
+        if self.tagged == 0 {
+            return NodeRef::Empty;
+        }
+
+        let ptr = self.tagged & !TAG_MASK;
+        unsafe {
+            match self.tagged & TAG_MASK {
+                TAG_NODE4 => NodeRef::Inner4(&*(ptr as *const _)),
+                TAG_NODE8 => NodeRef::Inner8(&*(ptr as *const _)),
+                TAG_NODE16 => NodeRef::Inner16(&*(ptr as *const _)),
+                TAG_NODE32 => NodeRef::Inner32(&*(ptr as *const _)),
+                TAG_NODE48 => NodeRef::Inner48(&*(ptr as *const _)),
+                TAG_NODE256 => NodeRef::Inner256(&*(ptr as *const _)),
+                TAG_SPAN => NodeRef::Span(&*(ptr as *const _)),
+                TAG_LEAF => NodeRef::Leaf(&*((ptr & !INLINE_LEAF) as *const _)),
+                _ => panic!("Invalid child tag"),
+            }
+        }
+    }
+
+    #[inline]
+    pub fn mut_base(&mut self) -> Option<&mut ArtNodeBase<P>> {
+        let ptr = self.tagged & !TAG_MASK;
+        if ptr == 0 {
+            return None;
+        }
+
+        unsafe {
+            match self.tagged & TAG_MASK {
+                TAG_NODE4 => Some(&mut (*(ptr as *mut ArtNode4<K, V, P>)).n),
+                TAG_NODE8 => Some(&mut (*(ptr as *mut ArtNode8<K, V, P>)).n),
+                TAG_NODE16 => Some(&mut (*(ptr as *mut ArtNode16<K, V, P>)).n),
+                TAG_NODE32 => Some(&mut (*(ptr as *mut ArtNode32<K, V, P>)).n),
+                TAG_NODE48 => Some(&mut (*(ptr as *mut ArtNode48<K, V, P>)).n),
+                TAG_NODE256 => Some(&mut (*(ptr as *mut ArtNode256<K, V, P>)).n),
+                TAG_SPAN => Some(&mut (*(ptr as *mut ArtNodeSpan<K, V, P>)).n),
+                _ => None,
+            }
+        }
+    }
+
+    // Starts loading the node into the cache, see `ArtTree::get_many`. Only the first cache line, which holds
+    // the header and (for small nodes) the keys.
+    #[inline]
+    pub fn prefetch(&self) {
+        prefetch((self.tagged & !TAG_MASK) as *const u8);
+    }
+
+    // Nodes whose child slots are not in the first cache line, see `prefetch_child`.
+    #[inline]
+    pub fn is_wide(&self) -> bool {
+        match self.tagged & TAG_MASK {
+            TAG_NODE48 | TAG_NODE256 | TAG_SPAN => self.tagged != 0,
+            _ => false,
+        }
+    }
+
+    // Starts loading the part of a large node which `key` leads to: the index entry of `ArtNode48`, the child
+    // of `ArtNode256` and span nodes. Reads the node header, so it should come some time after `prefetch`.
+    #[inline]
+    pub fn prefetch_child(&self, key: &[u8], depth: usize) {
+        let ptr = self.tagged & !TAG_MASK;
+        unsafe {
+            match self.tagged & TAG_MASK {
+                TAG_NODE48 => {
+                    let node = &*(ptr as *const ArtNode48<K, V, P>);
+                    if let Some(&byte) = key.get(depth + node.n.partial_len) {
+                        prefetch(&node.keys[byte as usize]);
+                    }
+                }
+                TAG_NODE256 => {
+                    let node = &*(ptr as *const ArtNode256<K, V, P>);
+                    if let Some(&byte) = key.get(depth + node.n.partial_len) {
+                        prefetch(&node.children[byte as usize] as *const _ as *const u8);
+                    }
+                }
+                TAG_SPAN => {
+                    let node = &*(ptr as *const ArtNodeSpan<K, V, P>);
+                    if let Some(slot) = ArtNodeSpan::<K, V, P>::slot(key, depth + node.n.partial_len) {
+                        prefetch(&node.children[slot] as *const _ as *const u8);
+                    }
+                }
+                _ => {}
             }
+        }
+    }
+
+    // Child slot for the key bytes at `depth`, together with the depth after it. Slot is a single key byte,
+    // or two of them for span nodes. None if the key is too short.
+    #[inline]
+    pub fn key_slot(&self, key: &[u8], depth: usize) -> Option<(usize, usize)> {
+        if self.tagged & TAG_MASK == TAG_SPAN {
+            ArtNodeSpan::<K, V, P>::slot(key, depth).map(|slot| (slot, depth + 2))
+        } else if depth < key.len() {
+            Some((key[depth] as usize, depth + 1))
         } else {
-            if mem::size_of::<V>() > SMALL_STRUCT {
-                ArtNode::LeafLargeValue(SmallStruct::new(key), Box::new(value))
-            } else {
-                ArtNode::LeafSmall(SmallStruct::new(key), SmallStruct::new(value))
+            None
+        }
+    }
+}
+
+impl<K: ArtKey, V, const P: usize> ChildPtr<K, V, P> {
+    // Child of an inner node under `slot` (see `key_slot`), None for leaves and empty slots.
+    #[inline]
+    pub fn child(&self, slot: usize) -> Option<&ChildPtr<K, V, P>> {
+        match self.get() {
+            NodeRef::Inner4(ptr) => ptr.find_child(slot as u8),
+            NodeRef::Inner8(ptr) => ptr.find_child(slot as u8),
+            NodeRef::Inner16(ptr) => ptr.find_child(slot as u8),
+            NodeRef::Inner32(ptr) => ptr.find_child(slot as u8),
+            NodeRef::Inner48(ptr) => ptr.find_child(slot as u8),
+            NodeRef::Inner256(ptr) => ptr.find_child(slot as u8),
+            NodeRef::Span(ptr) => ptr.find_child(slot),
+            _ => None,
+        }
+    }
+
+    // Same as `child`, but the child has to exist.
+    #[inline]
+    pub fn child_mut(&mut self, slot: usize) -> &mut ChildPtr<K, V, P> {
+        let ptr = self.tagged & !TAG_MASK;
+        let byte = slot as u8;
+        unsafe {
+            match self.tagged & TAG_MASK {
+                _ if ptr == 0 => panic!("Empty node has no children"),
+                TAG_NODE4 => (*(ptr as *mut ArtNode4<K, V, P>)).find_child_mut(byte),
+                TAG_NODE8 => (*(ptr as *mut ArtNode8<K, V, P>)).find_child_mut(byte),
+                TAG_NODE16 => (*(ptr as *mut ArtNode16<K, V, P>)).find_child_mut(byte),
+                TAG_NODE32 => (*(ptr as *mut ArtNode32<K, V, P>)).find_child_mut(byte),
+                TAG_NODE48 => (*(ptr as *mut ArtNode48<K, V, P>)).find_child_mut(byte),
+                TAG_NODE256 => (*(ptr as *mut ArtNode256<K, V, P>)).find_child_mut(byte),
+                TAG_SPAN => &mut (*(ptr as *mut ArtNodeSpan<K, V, P>)).children[slot],
+                _ => panic!("Leaf has no children"),
+            }
+        }
+    }
+
+    // Leftmost leaf of the subtree. Every leaf below a node shares the node's prefix, so the leaf key
+    // can be used to read the part of the prefix which did not fit in `partial`.
+    pub fn minimum_leaf(&self) -> &Leaf<K, V> {
+        let mut node = self;
+        loop {
+            node = match node.get() {
+                NodeRef::Empty => panic!("Empty node has no leaves"),
+                NodeRef::Inner4(ptr) => ptr.first_child(),
+                NodeRef::Inner8(ptr) => ptr.first_child(),
+                NodeRef::Inner16(ptr) => ptr.first_child(),
+                NodeRef::Inner32(ptr) => ptr.first_child(),
+                NodeRef::Inner48(ptr) => ptr.first_child(),
+                NodeRef::Inner256(ptr) => ptr.first_child(),
+                NodeRef::Span(ptr) => ptr.first_child(),
+                NodeRef::Leaf(leaf) => return leaf,
             }
         }
     }
+
+    // Rightmost leaf of the subtree.
+    pub fn maximum_leaf(&self) -> &Leaf<K, V> {
+        let mut node = self;
+        loop {
+            node = match node.get() {
+                NodeRef::Empty => panic!("Empty node has no leaves"),
+                NodeRef::Inner4(ptr) => ptr.last_child(),
+                NodeRef::Inner8(ptr) => ptr.last_child(),
+                NodeRef::Inner16(ptr) => ptr.last_child(),
+                NodeRef::Inner32(ptr) => ptr.last_child(),
+                NodeRef::Inner48(ptr) => ptr.last_child(),
+                NodeRef::Inner256(ptr) => ptr.last_child(),
+                NodeRef::Span(ptr) => ptr.last_child(),
+                NodeRef::Leaf(leaf) => return leaf,
+            }
+        }
+    }
+}
+
+impl<K, V, const P: usize> Drop for ChildPtr<K, V, P> {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+// Same as for the `Box`ed nodes the pointer replaces.
+unsafe impl<K: Send, V: Send, const P: usize> Send for ChildPtr<K, V, P> {}
+unsafe impl<K: Sync, V: Sync, const P: usize> Sync for ChildPtr<K, V, P> {}
+
+// Inserts `child` under `byte` into the first `len` (sorted) slots of `ArtNode4`..`ArtNode32`, shifting
+// the larger keys one slot to the right. Caller has to make sure there is a free slot.
+unsafe fn insert_sorted<K, V, const P: usize>(keys: &mut [u8], children: &mut [MaybeUninit<ChildPtr<K, V, P>>], len: usize, child: ArtNode<K, V, P>, byte: u8) {
+    let idx = keys[..len].iter().position(|&k| k > byte).unwrap_or(len);
+
+    keys.copy_within(idx..len, idx + 1);
+    let slots = children.as_mut_ptr();
+    ptr::copy(slots.add(idx), slots.add(idx + 1), len - idx);
+
+    keys[idx] = byte;
+    children[idx] = MaybeUninit::new(ChildPtr::new(child));
+}
+
+// Removes the (already emptied) child under `byte` from the first `len` (sorted) slots, shifting the
+// larger keys one slot to the left.
+unsafe fn remove_sorted<K, V, const P: usize>(keys: &mut [u8], children: &mut [MaybeUninit<ChildPtr<K, V, P>>], len: usize, byte: u8) {
+    let idx = match keys[..len].iter().position(|&k| k == byte) {
+        Some(idx) => idx,
+        None => panic!("Removing child not found"),
+    };
+
+    children[idx].assume_init_drop();
+
+    keys.copy_within(idx + 1..len, idx);
+    let slots = children.as_mut_ptr();
+    ptr::copy(slots.add(idx + 1), slots.add(idx), len - idx - 1);
+}
+
+fn uninit_children<K, V, const P: usize, const N: usize>() -> [MaybeUninit<ChildPtr<K, V, P>>; N] {
+    // An array of `MaybeUninit` does not need initialization
+    unsafe { MaybeUninit::uninit().assume_init() }
+}
+
+// Replaces `from` by the larger node `to`, which also gets the new `leaf`.
+fn grow_into<K, V, F, N, const P: usize>(from: &mut F, mut to: Box<N>, leaf: ArtNode<K, V, P>, byte: u8) -> ArtNode<K, V, P>
+    where F: ArtNodeTrait<K, V, P>, N: ArtNodeTrait<K, V, P>
+{
+    from.drain_into(&mut *to);
+    to.add_child(leaf, byte);
+    to.to_art_node()
+}
+
+// Number of equal leading bytes of `a` and `b`, compared a word at a time.
+#[inline]
+pub fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    let len = std::cmp::min(a.len(), b.len());
+    let mut i = 0;
+
+    while i + 8 <= len {
+        let diff = unsafe {
+            ptr::read_unaligned(a.as_ptr().add(i) as *const u64) ^ ptr::read_unaligned(b.as_ptr().add(i) as *const u64)
+        };
+        if diff != 0 {
+            // the first differing byte is the lowest one in memory order
+            return i + (u64::from_le(diff).trailing_zeros() / 8) as usize;
+        }
+        i += 8;
+    }
+
+    while i < len && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
+#[inline]
+fn prefetch(ptr: *const u8) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        use std::arch::x86_64::*;
+        _mm_prefetch::<_MM_HINT_T0>(ptr as *const i8);
+    }
+}
+
+// Replaces `from` by the smaller node `to`.
+fn shrink_into<K, V, F, N, const P: usize>(from: &mut F, mut to: Box<N>) -> ArtNode<K, V, P>
+    where F: ArtNodeTrait<K, V, P>, N: ArtNodeTrait<K, V, P>
+{
+    from.drain_into(&mut *to);
+    to.to_art_node()
 }
 
-impl ArtNodeBase {
+impl<const P: usize> ArtNodeBase<P> {
     pub fn new() -> Self {
         ArtNodeBase {
             num_children: 0,
             partial_len: 0,
-            partial: unsafe { mem::uninitialized() }
+            partial: [0; P],
         }
     }
 
-    pub fn compute_prefix_match<K: ArtKey>(&self, key: &K, depth: usize) -> usize {
-        for i in 0..self.partial_len {
-            if key.bytes()[i + depth] != self.partial[i] {
-                return i;
-            }
+    // Matches `key` against the stored part of the prefix only, ie. at most `P` bytes.
+    #[inline]
+    pub fn compute_prefix_match(&self, key: &[u8], depth: usize) -> usize {
+        match key.get(depth..) {
+            Some(rest) => common_prefix_len(&self.partial[..self.stored_prefix_len()], rest),
+            None => 0,
         }
-        self.partial_len
+    }
+
+    #[inline]
+    pub fn stored_prefix_len(&self) -> usize {
+        std::cmp::min(self.partial_len, P)
+    }
+
+    // Used when a node is replaced by one of another type.
+    fn copy_prefix_to(&self, to: &mut ArtNodeBase<P>) {
+        to.partial_len = self.partial_len;
+        to.partial = self.partial;
     }
 }
 
-impl<K, V> ArtNode4<K, V> {
+impl<K, V, const P: usize> ArtNode4<K, V, P> {
     pub fn new() -> Self {
         ArtNode4 {
             n: ArtNodeBase::new(),
-            keys: unsafe { mem::uninitialized() },
-            children: unsafe { mem::uninitialized() },
+            keys: [0; 4],
+            children: uninit_children(),
         }
     }
 }
 
-impl<K,V> Drop for ArtNode4<K,V> {
-    fn drop(&mut self) {
-        for i in 0..self.n.num_children {
-            drop(&mut self.children[i as usize]);
+impl<K, V, const P: usize> ArtNode4<K, V, P> {
+    // Keys are sorted, so we can stop at the first key larger than `byte`.
+    #[inline]
+    fn find_index(&self, byte: u8) -> Option<usize> {
+        for i in 0..self.n.num_children as usize {
+            if self.keys[i] >= byte {
+                return if self.keys[i] == byte { Some(i) } else { None };
+            }
+        }
+        None
+    }
+}
+
+impl<K, V, const P: usize> ArtNode8<K, V, P> {
+    pub fn new() -> Self {
+        ArtNode8 {
+            n: ArtNodeBase::new(),
+            // keys are always initialized, lookup loads all 8 of them
+            keys: [0; 8],
+            children: uninit_children(),
         }
     }
+
+    // Compares `byte` against all 8 keys at once, within a single u64. The lowest set bit of the zero
+    // byte test is exact (only bits above it can be false positives), and keys are unique, so the lowest
+    // match is the only one. Matches in the unused slots are masked out.
+    #[inline]
+    fn find_index(&self, byte: u8) -> Option<usize> {
+        const LO: u64 = 0x0101_0101_0101_0101;
+        const HI: u64 = 0x8080_8080_8080_8080;
+
+        let x = u64::from_le_bytes(self.keys) ^ (LO * byte as u64);
+        let bitfield = x.wrapping_sub(LO) & !x & HI;
+        let idx = bitfield.trailing_zeros() as usize / 8;
+
+        if idx < self.n.num_children as usize { Some(idx) } else { None }
+    }
 }
 
-impl<K, V> ArtNode16<K, V> {
+impl<K, V, const P: usize> ArtNode16<K, V, P> {
     pub fn new() -> Self {
         ArtNode16 {
             n: ArtNodeBase::new(),
-            keys: unsafe { mem::uninitialized() },
-            children: unsafe { mem::uninitialized() }
+            // keys are always initialized, SIMD lookup loads all 16 of them
+            keys: [0; 16],
+            children: uninit_children(),
+        }
+    }
+
+    // Compares `byte` against all 16 keys at once, and masks out the unused slots.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    fn find_index(&self, byte: u8) -> Option<usize> {
+        use std::arch::x86_64::*;
+
+        let bitfield = unsafe {
+            let cmp = _mm_cmpeq_epi8(
+                _mm_set1_epi8(byte as i8),
+                _mm_loadu_si128(self.keys.as_ptr() as *const __m128i));
+            _mm_movemask_epi8(cmp) as u32 & ((1u32 << self.n.num_children) - 1)
+        };
+
+        if bitfield != 0 {
+            Some(bitfield.trailing_zeros() as usize)
+        } else {
+            None
+        }
+    }
+
+    // NEON has no movemask, so narrow the compare result to 4 bits per key instead.
+    #[cfg(target_arch = "aarch64")]
+    #[inline]
+    fn find_index(&self, byte: u8) -> Option<usize> {
+        use std::arch::aarch64::*;
+
+        let mut bitfield = unsafe {
+            let cmp = vceqq_u8(vdupq_n_u8(byte), vld1q_u8(self.keys.as_ptr()));
+            let narrowed = vshrn_n_u16::<4>(vreinterpretq_u16_u8(cmp));
+            vget_lane_u64::<0>(vreinterpret_u64_u8(narrowed))
+        };
+
+        if self.n.num_children < 16 {
+            bitfield &= (1u64 << (4 * self.n.num_children)) - 1;
+        }
+
+        if bitfield != 0 {
+            Some(bitfield.trailing_zeros() as usize / 4)
+        } else {
+            None
         }
     }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    #[inline]
+    fn find_index(&self, byte: u8) -> Option<usize> {
+        self.keys[..self.n.num_children as usize].binary_search(&byte).ok()
+    }
 }
 
-impl<K,V> Drop for ArtNode16<K,V> {
-    fn drop(&mut self) {
-        for i in 0..self.n.num_children {
-            drop(&mut self.children[i as usize]);
+impl<K, V, const P: usize> ArtNode32<K, V, P> {
+    pub fn new() -> Self {
+        ArtNode32 {
+            n: ArtNodeBase::new(),
+            // keys are always initialized, SIMD lookup loads all 32 of them
+            keys: [0; 32],
+            children: uninit_children(),
+        }
+    }
+
+    // Same as `ArtNode16::find_index`, with the keys compared in two halves.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    fn find_index(&self, byte: u8) -> Option<usize> {
+        use std::arch::x86_64::*;
+
+        let bitfield = unsafe {
+            let needle = _mm_set1_epi8(byte as i8);
+            let lo = _mm_cmpeq_epi8(needle, _mm_loadu_si128(self.keys.as_ptr() as *const __m128i));
+            let hi = _mm_cmpeq_epi8(needle, _mm_loadu_si128(self.keys.as_ptr().add(16) as *const __m128i));
+            let mask = (_mm_movemask_epi8(lo) as u32 as u64) | ((_mm_movemask_epi8(hi) as u32 as u64) << 16);
+            mask & ((1u64 << self.n.num_children) - 1)
+        };
+
+        if bitfield != 0 {
+            Some(bitfield.trailing_zeros() as usize)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    #[inline]
+    fn find_index(&self, byte: u8) -> Option<usize> {
+        self.keys[..self.n.num_children as usize].binary_search(&byte).ok()
+    }
+}
+
+// Only the first `num_children` children are initialized.
+macro_rules! drop_sorted_children {
+    ($($node: ident),*) => {
+        $(
+            impl<K, V, const P: usize> Drop for $node<K, V, P> {
+                fn drop(&mut self) {
+                    for i in 0..self.n.num_children as usize {
+                        unsafe { self.children[i].assume_init_drop() };
+                    }
+                }
+            }
+        )*
+    }
+}
+
+drop_sorted_children!(ArtNode4, ArtNode8, ArtNode16, ArtNode32);
+
+impl ChildBitmap {
+    #[inline]
+    pub fn new() -> Self {
+        ChildBitmap { words: [0; 4] }
+    }
+
+    #[inline]
+    pub fn set(&mut self, byte: u8) {
+        self.words[byte as usize >> 6] |= 1 << (byte & 63);
+    }
+
+    #[inline]
+    pub fn clear(&mut self, byte: u8) {
+        self.words[byte as usize >> 6] &= !(1 << (byte & 63));
+    }
+
+    #[inline]
+    pub fn first(&self) -> Option<u8> {
+        self.words.iter().position(|&word| word != 0)
+            .map(|i| (i << 6) as u8 | self.words[i].trailing_zeros() as u8)
+    }
+
+    #[inline]
+    pub fn last(&self) -> Option<u8> {
+        self.words.iter().rposition(|&word| word != 0)
+            .map(|i| (i << 6) as u8 | (63 - self.words[i].leading_zeros()) as u8)
+    }
+
+    // Smallest set byte larger than `after`.
+    #[inline]
+    pub fn next_child(&self, after: u8) -> Option<u8> {
+        if after == 255 {
+            return None;
+        }
+
+        let from = after as usize + 1;
+        let mut i = from >> 6;
+        let mut word = self.words[i] & (!0 << (from & 63));
+        loop {
+            if word != 0 {
+                return Some((i << 6) as u8 | word.trailing_zeros() as u8);
+            }
+            i += 1;
+            if i == 4 {
+                return None;
+            }
+            word = self.words[i];
+        }
+    }
+
+    // Largest set byte smaller than `before`.
+    #[inline]
+    pub fn prev_child(&self, before: u8) -> Option<u8> {
+        if before == 0 {
+            return None;
+        }
+
+        let to = before as usize - 1;
+        let mut i = to >> 6;
+        let mut word = self.words[i] & (!0 >> (63 - (to & 63)));
+        loop {
+            if word != 0 {
+                return Some((i << 6) as u8 | (63 - word.leading_zeros()) as u8);
+            }
+            if i == 0 {
+                return None;
+            }
+            i -= 1;
+            word = self.words[i];
+        }
+    }
+
+    // Set bytes in ascending order, or descending with `rev`.
+    #[inline]
+    pub fn iter(&self) -> ChildBitmapIter {
+        ChildBitmapIter { bitmap: *self, front: None, back: None }
+    }
+}
+
+impl Iterator for ChildBitmapIter {
+    type Item = u8;
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        let next = match self.front {
+            Some(byte) => self.bitmap.next_child(byte),
+            None => self.bitmap.first(),
+        };
+
+        match (next, self.back) {
+            (Some(byte), Some(back)) if byte >= back => None,
+            (Some(byte), _) => {
+                self.front = Some(byte);
+                Some(byte)
+            }
+            (None, _) => None,
+        }
+    }
+}
+
+impl DoubleEndedIterator for ChildBitmapIter {
+    #[inline]
+    fn next_back(&mut self) -> Option<u8> {
+        let next = match self.back {
+            Some(byte) => self.bitmap.prev_child(byte),
+            None => self.bitmap.last(),
+        };
+
+        match (next, self.front) {
+            (Some(byte), Some(front)) if byte <= front => None,
+            (Some(byte), _) => {
+                self.back = Some(byte);
+                Some(byte)
+            }
+            (None, _) => None,
         }
     }
 }
 
-impl<K, V> ArtNode48<K, V> {
+impl<K, V, const P: usize> ArtNode48<K, V, P> {
     pub fn new() -> Self {
         ArtNode48 {
             n: ArtNodeBase::new(),
+            occupied: ChildBitmap::new(),
             keys: [EMPTY_CELL; 256],
-            children: unsafe { mem::uninitialized() }
+            children: uninit_children(),
         }
     }
 }
 
-impl<K,V> Drop for ArtNode48<K,V> {
+impl<K, V, const P: usize> Drop for ArtNode48<K, V, P> {
     fn drop(&mut self) {
-        for i in 0..256 {
-            if self.keys[i] != EMPTY_CELL {
-                drop(&mut self.children[self.keys[i] as usize - 1]);
-            }
+        for i in 0..self.n.num_children as usize {
+            unsafe { self.children[i].assume_init_drop() };
         }
     }
 }
 
-impl<K, V> ArtNode256<K, V> {
+impl<K, V, const P: usize> ArtNode256<K, V, P> {
     pub fn new() -> Self {
         ArtNode256 {
             n: ArtNodeBase::new(),
-            children: unsafe { make_array!(256, ArtNode::Empty) }
+            occupied: ChildBitmap::new(),
+            children: std::array::from_fn(|_| ChildPtr::empty()),
         }
     }
 }
 
-impl<K: ArtKey, V> ArtNodeTrait<K, V> for ArtNode4<K, V> {
-    fn add_child(&mut self, child: ArtNode<K, V>, byte: u8) {
-        let idx = self.n.num_children as usize;
+impl<K, V, const P: usize> ArtNodeSpan<K, V, P> {
+    // Allocated directly on the heap, the node is too large to be built on the stack.
+    pub fn new() -> Box<Self> {
+        let layout = std::alloc::Layout::new::<Self>();
         unsafe {
-            ptr::write(&mut self.children[idx] as *mut ArtNode<K,V>, child);
-            ptr::write(&mut self.keys[idx] as *mut u8, byte);
+            let ptr = std::alloc::alloc_zeroed(layout) as *mut Self;
+            if ptr.is_null() {
+                std::alloc::handle_alloc_error(layout);
+            }
+            Box::from_raw(ptr)
         }
-        self.n.num_children += 1;
     }
 
-    fn clean_child(&mut self, byte: u8) -> bool {
-        for i in 0..self.n.num_children {
-            if self.keys[i as usize] == byte {
-                self.keys[i as usize] = EMPTY_CELL;
-                self.n.num_children -= 1;
+    #[inline]
+    pub fn slot(key: &[u8], depth: usize) -> Option<usize> {
+        if depth + 1 < key.len() {
+            Some((key[depth] as usize) << 8 | key[depth + 1] as usize)
+        } else {
+            None
+        }
+    }
 
-                self.children.swap(i as usize, self.n.num_children as usize);
-                self.keys.swap(i as usize, self.n.num_children as usize);
+    // `ArtNode256` can be replaced by a span node once all of its children are `ArtNode256`s without prefix.
+    pub fn can_replace(node: &ArtNode256<K, V, P>) -> bool {
+        node.n.num_children == 256 && node.children.iter().all(|child| match child.get() {
+            NodeRef::Inner256(child) => child.n.partial_len == 0,
+            _ => false,
+        })
+    }
 
-                return self.n.num_children == 0;
+    // Moves the children of the `ArtNode256` children of `node` into a new span node, see `can_replace`.
+    pub fn from_node256(mut node: Box<ArtNode256<K, V, P>>, cache: &mut NodeCache<K, V, P>) -> Box<Self> {
+        let mut span = Self::new();
+        node.n.copy_prefix_to(&mut span.n);
+
+        for hi in 0..256 {
+            let mut child = match node.children[hi].take() {
+                ArtNode::Inner256(child) => child,
+                _ => panic!("Span node can only replace ArtNode256 children"),
+            };
+
+            for lo in child.occupied.iter() {
+                span.children[hi << 8 | lo as usize] = mem::replace(&mut child.children[lo as usize], ChildPtr::empty());
             }
+            span.counts[hi] = child.n.num_children;
+            cache.recycle256(child);
         }
-        panic!("Removing child not found");
-    }
 
-    fn is_full(&self) -> bool {
-        self.n.num_children >= 4
+        node.n.num_children = 0;
+        cache.recycle256(node);
+        span
     }
 
-    fn to_art_node(self: Box<Self>) -> ArtNode<K,V> {
-        ArtNode::Inner4(self)
+    #[inline]
+    pub fn find_child(&self, slot: usize) -> Option<&ChildPtr<K, V, P>> {
+        let child = &self.children[slot];
+        if child.is_empty() { None } else { Some(child) }
     }
 
-    fn shrink(self) -> ArtNode<K,V> {
-        ArtNode::Empty
+    pub fn add_child(&mut self, child: ArtNode<K, V, P>, slot: usize) {
+        self.counts[slot >> 8] += 1;
+        self.children[slot] = ChildPtr::new(child);
     }
 
-    fn grow_and_add(mut self, leaf: ArtNode<K, V>, byte: u8) -> ArtNode<K, V> {
-        let mut new_node = Box::new(ArtNode16::new());
-        new_node.n.partial_len = self.n.partial_len;
+    // Same as `ArtNodeTrait::clean_child`. Span node is split once any of its `ArtNode256`s would shrink.
+    pub fn clean_child(&mut self, slot: usize, config: &ArtConfig) -> bool {
+        self.counts[slot >> 8] -= 1;
+        self.counts[slot >> 8] <= config.shrink_node256
+    }
 
-        unsafe {
-            ptr::copy_nonoverlapping(
-                self.n.partial.as_ptr(),
-                new_node.n.partial.as_mut_ptr(),
-                self.n.partial.len());
+    pub fn first_child(&self) -> &ChildPtr<K, V, P> {
+        match self.counts.iter().position(|&count| count > 0) {
+            Some(hi) => self.children[hi << 8..].iter().find(|child| !child.is_empty()).unwrap(),
+            None => panic!("No children"),
         }
+    }
 
-        new_node.add_child(leaf, byte);
-
-        for i in 0..4 {
-            let child = std::mem::replace(&mut self.children[i as usize], ArtNode::Empty);
-            new_node.add_child(child, self.keys[i as usize]);
+    pub fn last_child(&self) -> &ChildPtr<K, V, P> {
+        match self.counts.iter().rposition(|&count| count > 0) {
+            Some(hi) => self.children[..(hi + 1) << 8].iter().rev().find(|child| !child.is_empty()).unwrap(),
+            None => panic!("No children"),
         }
-
-        ArtNode::Inner16(new_node)
     }
 
-    fn mut_base(&mut self) -> &mut ArtNodeBase {
-        &mut self.n
+    pub fn len(&self) -> usize {
+        self.counts.iter().map(|&count| count as usize).sum()
     }
 
-    fn base(&self) -> &ArtNodeBase {
-        &self.n
-    }
+    // Moves the children into an `ArtNode256` of `ArtNode256`s, which shrink if they are small enough. The
+    // span node is left empty.
+    pub fn split(&mut self, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P>
+        where K: ArtKey
+    {
+        let mut node = cache.node256();
+        self.n.copy_prefix_to(&mut node.n);
+
+        for hi in 0..256 {
+            if self.counts[hi] == 0 {
+                continue;
+            }
 
-    fn find_child_mut(&mut self, byte: u8) -> &mut ArtNode<K, V> {
-        for i in 0..self.n.num_children {
-            if self.keys[i as usize] == byte {
-                return &mut self.children[i as usize];
+            let mut child = cache.node256();
+            for lo in 0..256 {
+                let slot = &mut self.children[hi << 8 | lo];
+                if !slot.is_empty() {
+                    child.add_child(slot.take(), lo as u8);
+                }
             }
+            self.counts[hi] = 0;
+
+            let child = if child.n.num_children <= config.shrink_node256 {
+                child.shrink(config, cache)
+            } else {
+                ArtNode::Inner256(child)
+            };
+            node.add_child(child, hi as u8);
         }
-        panic!("No requested child");
+
+        ArtNode::Inner256(node)
     }
 
-    fn find_child(&self, byte: u8) -> Option<&ArtNode<K, V>> {
-        for i in 0..self.n.num_children {
-            if self.keys[i as usize] == byte {
-                return Some(&self.children[i as usize]);
+    pub fn take_children(&mut self, out: &mut Vec<ArtNode<K, V, P>>) {
+        for child in self.children.iter_mut() {
+            if !child.is_empty() {
+                out.push(child.take());
             }
         }
-        None
+        self.counts = [0; 256];
     }
 
-    fn has_child(&self, byte: u8) -> bool {
-        for i in 0..self.n.num_children {
-            if self.keys[i as usize] == byte {
-                return true;
+    pub fn for_each_child<'a, F: FnMut(usize, &'a ChildPtr<K, V, P>)>(&'a self, mut f: F) where K: 'a, V: 'a {
+        for (slot, child) in self.children.iter().enumerate() {
+            if !child.is_empty() {
+                f(slot, child);
             }
         }
-        false
     }
 }
 
-impl<K: ArtKey, V> ArtNodeTrait<K, V> for ArtNode16<K, V> {
-    fn add_child(&mut self, child: ArtNode<K, V>, byte: u8) {
-        let idx = self.n.num_children as usize;
-        unsafe {
-            ptr::write(&mut self.children[idx] as *mut ArtNode<K,V>, child);
-            ptr::write(&mut self.keys[idx] as *mut u8, byte);
+// Trait methods shared by the nodes with sorted `keys` (`ArtNode4`..`ArtNode32`), which differ only in
+// capacity, the shrink threshold of `clean_child` and the node types they grow and shrink into.
+//
+macro_rules! sorted_node_methods {
+    ($variant: ident, $len: expr, $config: ident => $shrink_at: expr) => {
+        fn add_child(&mut self, child: ArtNode<K, V, P>, byte: u8) {
+            unsafe {
+                insert_sorted(&mut self.keys[..], &mut self.children[..], self.n.num_children as usize, child, byte);
+            }
+            self.n.num_children += 1;
         }
-        self.n.num_children += 1;
-    }
 
-    fn clean_child(&mut self, byte: u8) -> bool {
-        for i in 0..self.n.num_children {
-            if self.keys[i as usize] == byte {
-                self.keys[i as usize] = EMPTY_CELL;
-                self.n.num_children -= 1;
+        fn clean_child(&mut self, byte: u8, $config: &ArtConfig) -> bool {
+            unsafe {
+                remove_sorted(&mut self.keys[..], &mut self.children[..], self.n.num_children as usize, byte);
+            }
+            self.n.num_children -= 1;
+            self.n.num_children <= $shrink_at
+        }
+
+        fn is_full(&self) -> bool {
+            self.n.num_children >= $len
+        }
 
-                self.children.swap(i as usize, self.n.num_children as usize);
-                self.keys.swap(i as usize, self.n.num_children as usize);
+        fn to_art_node(self: Box<Self>) -> ArtNode<K, V, P> {
+            ArtNode::$variant(self)
+        }
 
-                return self.n.num_children <= 2
+        fn drain_into<N: ArtNodeTrait<K, V, P>>(&mut self, to: &mut N) {
+            self.n.copy_prefix_to(to.mut_base());
+            for i in 0..self.n.num_children as usize {
+                let child = unsafe { self.children[i].assume_init_read() }.take();
+                to.add_child(child, self.keys[i]);
             }
+            self.n.num_children = 0;
         }
-        panic!("Removing child not found");
-    }
 
-    fn is_full(&self) -> bool {
-        self.n.num_children >= 16
-    }
+        fn mut_base(&mut self) -> &mut ArtNodeBase<P> {
+            &mut self.n
+        }
 
-    fn to_art_node(self: Box<Self>) -> ArtNode<K,V> {
-        ArtNode::Inner16(self)
-    }
+        fn base(&self) -> &ArtNodeBase<P> {
+            &self.n
+        }
 
-    fn shrink(mut self) -> ArtNode<K,V> {
-        let mut new_node = Box::new(ArtNode4::new());
+        fn find_child_mut(&mut self, byte: u8) -> &mut ChildPtr<K, V, P> {
+            match self.find_index(byte) {
+                Some(idx) => unsafe { self.children[idx].assume_init_mut() },
+                None => panic!("No requested child"),
+            }
+        }
 
-        new_node.n.partial_len = self.n.partial_len;
+        fn find_child(&self, byte: u8) -> Option<&ChildPtr<K, V, P>> {
+            self.find_index(byte).map(|idx| unsafe { self.children[idx].assume_init_ref() })
+        }
 
-        unsafe {
-            ptr::copy_nonoverlapping(
-                self.n.partial.as_ptr(),
-                new_node.n.partial.as_mut_ptr(),
-                self.n.partial.len());
+        fn has_child(&self, byte: u8) -> bool {
+            self.find_index(byte).is_some()
         }
 
-        for i in 0..self.n.num_children {
-            let child = std::mem::replace(&mut self.children[i as usize], ArtNode::Empty);
-            new_node.add_child(child, self.keys[i as usize]);
+        fn first_child(&self) -> &ChildPtr<K, V, P> {
+            unsafe { self.children[0].assume_init_ref() }
         }
 
-        ArtNode::Inner4(new_node)
+        fn last_child(&self) -> &ChildPtr<K, V, P> {
+            unsafe { self.children[self.n.num_children as usize - 1].assume_init_ref() }
+        }
+
+        fn take_children(&mut self, out: &mut Vec<ArtNode<K, V, P>>) {
+            for i in 0..self.n.num_children as usize {
+                out.push(unsafe { self.children[i].assume_init_read() }.take());
+            }
+            self.n.num_children = 0;
+        }
+
+        fn for_each_child<'a, F: FnMut(u8, &'a ChildPtr<K, V, P>)>(&'a self, mut f: F) where K: 'a, V: 'a {
+            for i in 0..self.n.num_children as usize {
+                f(self.keys[i], unsafe { self.children[i].assume_init_ref() });
+            }
+        }
     }
+}
 
-    fn grow_and_add(mut self, leaf: ArtNode<K, V>, byte: u8) -> ArtNode<K, V> {
-        let mut new_node = Box::new(ArtNode48::new());
-        new_node.n.partial_len = self.n.partial_len;
+impl<K: ArtKey, V, const P: usize> ArtNodeTrait<K, V, P> for ArtNode4<K, V, P> {
+    sorted_node_methods!(Inner4, 4, _config => 1);
 
-        unsafe {
-            ptr::copy_nonoverlapping(
-                self.n.partial.as_ptr(),
-                new_node.n.partial.as_mut_ptr(),
-                self.n.partial.len());
+    // Node with a single child is merged into the child. Leaf simply replaces the node, inner
+    // child gets our prefix and the key byte prepended to its own prefix.
+    fn shrink(mut self: Box<Self>, _config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        if self.n.num_children == 0 {
+            cache.recycle4(self);
+            return ArtNode::Empty;
         }
 
-        new_node.add_child(leaf, byte);
+        let byte = self.keys[0];
+        let mut child = unsafe { self.children[0].assume_init_read() };
+        self.n.num_children = 0;
+
+        if let Some(base) = child.mut_base() {
+            let mut partial = [0; P];
+            let mut len = self.n.stored_prefix_len();
+            partial[..len].copy_from_slice(&self.n.partial[..len]);
+
+            if len < P {
+                partial[len] = byte;
+                len += 1;
+            }
+
+            let copy_len = std::cmp::min(P - len, base.stored_prefix_len());
+            partial[len..len + copy_len].copy_from_slice(&base.partial[..copy_len]);
 
-        for i in 0..16 {
-            let child = std::mem::replace(&mut self.children[i], ArtNode::Empty);
-            new_node.add_child(child, self.keys[i]);
+            base.partial = partial;
+            base.partial_len += self.n.partial_len + 1;
         }
 
-        ArtNode::Inner48(new_node)
+        cache.recycle4(self);
+        child.take()
     }
 
-    fn mut_base(&mut self) -> &mut ArtNodeBase {
-        &mut self.n
+    fn grow_and_add(mut self: Box<Self>, leaf: ArtNode<K, V, P>, byte: u8, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = if config.intermediate_nodes {
+            grow_into(&mut *self, cache.node8(), leaf, byte)
+        } else {
+            grow_into(&mut *self, cache.node16(), leaf, byte)
+        };
+        cache.recycle4(self);
+        node
     }
+}
 
-    fn base(&self) -> &ArtNodeBase {
-        &self.n
+impl<K: ArtKey, V, const P: usize> ArtNodeTrait<K, V, P> for ArtNode8<K, V, P> {
+    sorted_node_methods!(Inner8, 8, config => config.shrink_node8);
+
+    fn shrink(mut self: Box<Self>, _config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = shrink_into(&mut *self, cache.node4());
+        cache.recycle8(self);
+        node
     }
 
-    fn find_child_mut(&mut self, byte: u8) -> &mut ArtNode<K, V> {
-        // TODO: use SIMD here
-        //
-        for i in 0..self.n.num_children {
-            if self.keys[i as usize] == byte {
-                return &mut self.children[i as usize];
-            }
-        }
-        panic!("No requested child");
+    fn grow_and_add(mut self: Box<Self>, leaf: ArtNode<K, V, P>, byte: u8, _config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = grow_into(&mut *self, cache.node16(), leaf, byte);
+        cache.recycle8(self);
+        node
     }
+}
 
-    fn find_child(&self, byte: u8) -> Option<&ArtNode<K, V>> {
-        // TODO: use SIMD here
-        //
-        for i in 0..self.n.num_children {
-            if self.keys[i as usize] == byte {
-                return Some(&self.children[i as usize]);
-            }
-        }
-        None
+impl<K: ArtKey, V, const P: usize> ArtNodeTrait<K, V, P> for ArtNode16<K, V, P> {
+    sorted_node_methods!(Inner16, 16, config => config.shrink_node16);
+
+    fn shrink(mut self: Box<Self>, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = if config.intermediate_nodes {
+            shrink_into(&mut *self, cache.node8())
+        } else {
+            shrink_into(&mut *self, cache.node4())
+        };
+        cache.recycle16(self);
+        node
     }
 
-    fn has_child(&self, byte: u8) -> bool {
-        for i in 0..self.n.num_children {
-            if self.keys[i as usize] == byte {
-                return true;
-            }
-        }
-        false
+    fn grow_and_add(mut self: Box<Self>, leaf: ArtNode<K, V, P>, byte: u8, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = if config.intermediate_nodes {
+            grow_into(&mut *self, cache.node32(), leaf, byte)
+        } else {
+            grow_into(&mut *self, cache.node48(), leaf, byte)
+        };
+        cache.recycle16(self);
+        node
     }
 }
 
-impl<K: ArtKey, V> ArtNodeTrait<K, V> for ArtNode48<K, V> {
-    fn add_child(&mut self, child: ArtNode<K, V>, byte: u8) {
-        unsafe {
-            let idx = self.n.num_children as usize;
-            ptr::write(&mut self.children[idx] as *mut ArtNode<K,V>, child);
-        }
+impl<K: ArtKey, V, const P: usize> ArtNodeTrait<K, V, P> for ArtNode32<K, V, P> {
+    sorted_node_methods!(Inner32, 32, config => config.shrink_node32);
+
+    fn shrink(mut self: Box<Self>, _config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = shrink_into(&mut *self, cache.node16());
+        cache.recycle32(self);
+        node
+    }
+
+    fn grow_and_add(mut self: Box<Self>, leaf: ArtNode<K, V, P>, byte: u8, _config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = grow_into(&mut *self, cache.node48(), leaf, byte);
+        cache.recycle32(self);
+        node
+    }
+}
+
+impl<K: ArtKey, V, const P: usize> ArtNodeTrait<K, V, P> for ArtNode48<K, V, P> {
+    fn add_child(&mut self, child: ArtNode<K, V, P>, byte: u8) {
+        self.children[self.n.num_children as usize] = MaybeUninit::new(ChildPtr::new(child));
         self.n.num_children += 1;
         self.keys[byte as usize] = self.n.num_children as u8;
+        self.occupied.set(byte);
     }
 
-    fn clean_child(&mut self, byte: u8) -> bool {
+    // Children are kept compact in `children[..num_children]`, so that `add_child` can always use the
+    // slot at `num_children`. The last child is moved into the freed slot and its key index is updated.
+    fn clean_child(&mut self, byte: u8, config: &ArtConfig) -> bool {
+        let slot = self.keys[byte as usize] as usize - 1;
+        let last = self.n.num_children as usize - 1;
+
+        unsafe {
+            self.children[slot].assume_init_drop();
+
+            if slot != last {
+                self.children[slot] = MaybeUninit::new(self.children[last].assume_init_read());
+
+                match self.occupied.iter().find(|&b| self.keys[b as usize] as usize == last + 1) {
+                    Some(moved_byte) => self.keys[moved_byte as usize] = (slot + 1) as u8,
+                    None => panic!("Moved child not found"),
+                }
+            }
+        }
+
         self.keys[byte as usize] = EMPTY_CELL;
+        self.occupied.clear(byte);
         self.n.num_children -= 1;
-        self.n.num_children <= 10
+        self.n.num_children <= config.shrink_node48
     }
 
     fn is_full(&self) -> bool {
         self.n.num_children >= 48
     }
 
-    fn to_art_node(self: Box<Self>) -> ArtNode<K,V> {
+    fn to_art_node(self: Box<Self>) -> ArtNode<K, V, P> {
         ArtNode::Inner48(self)
     }
 
-    fn shrink(mut self) -> ArtNode<K,V> {
-        let mut new_node = Box::new(ArtNode16::new());
-        new_node.n.partial_len = self.n.partial_len;
+    fn shrink(mut self: Box<Self>, config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = if config.intermediate_nodes {
+            shrink_into(&mut *self, cache.node32())
+        } else {
+            shrink_into(&mut *self, cache.node16())
+        };
+        cache.recycle48(self);
+        node
+    }
 
-        unsafe {
-            ptr::copy_nonoverlapping(
-                self.n.partial.as_ptr(),
-                new_node.n.partial.as_mut_ptr(),
-                self.n.partial.len());
-        }
+    fn grow_and_add(mut self: Box<Self>, leaf: ArtNode<K, V, P>, byte: u8, _config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = grow_into(&mut *self, cache.node256(), leaf, byte);
+        cache.recycle48(self);
+        node
+    }
 
-        for i in 0..256 {
-            if self.keys[i] != EMPTY_CELL {
-                let child = std::mem::replace(&mut self.children[self.keys[i] as usize - 1], ArtNode::Empty);
-                new_node.add_child(child, i as u8);
-            }
+    fn drain_into<N: ArtNodeTrait<K, V, P>>(&mut self, to: &mut N) {
+        self.n.copy_prefix_to(to.mut_base());
+        for byte in self.occupied.iter() {
+            let child = unsafe { self.children[self.keys[byte as usize] as usize - 1].assume_init_read() }.take();
+            to.add_child(child, byte);
         }
+        self.occupied = ChildBitmap::new();
+        self.n.num_children = 0;
+    }
 
-        ArtNode::Inner16(new_node)
+    fn mut_base(&mut self) -> &mut ArtNodeBase<P> {
+        &mut self.n
     }
 
-    fn grow_and_add(mut self, leaf: ArtNode<K, V>, byte: u8) -> ArtNode<K, V> {
-        let mut new_node = Box::new(ArtNode256::new());
-        new_node.n.partial_len = self.n.partial_len;
+    fn base(&self) -> &ArtNodeBase<P> {
+        &self.n
+    }
 
-        unsafe {
-            ptr::copy_nonoverlapping(
-                self.n.partial.as_ptr(),
-                new_node.n.partial.as_mut_ptr(),
-                self.n.partial.len());
+    fn find_child_mut(&mut self, byte: u8) -> &mut ChildPtr<K, V, P> {
+        match self.keys[byte as usize] {
+            EMPTY_CELL => panic!("No requested child"),
+            idx => unsafe { self.children[idx as usize - 1].assume_init_mut() },
         }
+    }
 
-        new_node.add_child(leaf, byte);
-
-        for i in 0..256 {
-            if self.keys[i] != EMPTY_CELL {
-                let child = std::mem::replace(&mut self.children[self.keys[i] as usize - 1], ArtNode::Empty);
-                new_node.add_child(child, i as u8);
-            }
+    fn find_child(&self, byte: u8) -> Option<&ChildPtr<K, V, P>> {
+        match self.keys[byte as usize] {
+            EMPTY_CELL => None,
+            idx => Some(unsafe { self.children[idx as usize - 1].assume_init_ref() }),
         }
-
-        ArtNode::Inner256(new_node)
     }
 
-    fn mut_base(&mut self) -> &mut ArtNodeBase {
-        &mut self.n
+    fn has_child(&self, byte: u8) -> bool {
+        self.keys[byte as usize] != EMPTY_CELL
     }
 
-    fn base(&self) -> &ArtNodeBase {
-        &self.n
+    fn first_child(&self) -> &ChildPtr<K, V, P> {
+        match self.occupied.first() {
+            Some(byte) => unsafe { self.children[self.keys[byte as usize] as usize - 1].assume_init_ref() },
+            None => panic!("No children"),
+        }
     }
 
-    fn find_child_mut(&mut self, byte: u8) -> &mut ArtNode<K, V> {
-        &mut self.children[self.keys[byte as usize] as usize - 1]
+    fn last_child(&self) -> &ChildPtr<K, V, P> {
+        match self.occupied.last() {
+            Some(byte) => unsafe { self.children[self.keys[byte as usize] as usize - 1].assume_init_ref() },
+            None => panic!("No children"),
+        }
     }
 
-    fn find_child(&self, byte: u8) -> Option<&ArtNode<K, V>> {
-        if self.keys[byte as usize] == EMPTY_CELL {
-            None
-        } else {
-            Some(&self.children[self.keys[byte as usize] as usize - 1])
+    // Children are compact, see `clean_child`.
+    fn take_children(&mut self, out: &mut Vec<ArtNode<K, V, P>>) {
+        for i in 0..self.n.num_children as usize {
+            out.push(unsafe { self.children[i].assume_init_read() }.take());
         }
+        self.occupied = ChildBitmap::new();
+        self.n.num_children = 0;
     }
 
-    fn has_child(&self, byte: u8) -> bool {
-        self.keys[byte as usize] != EMPTY_CELL
+    fn for_each_child<'a, F: FnMut(u8, &'a ChildPtr<K, V, P>)>(&'a self, mut f: F) where K: 'a, V: 'a {
+        for byte in self.occupied.iter() {
+            f(byte, unsafe { self.children[self.keys[byte as usize] as usize - 1].assume_init_ref() });
+        }
     }
 }
 
-impl<K: ArtKey, V> ArtNodeTrait<K, V> for ArtNode256<K, V> {
-    fn add_child(&mut self, child: ArtNode<K, V>, byte: u8) {
+impl<K: ArtKey, V, const P: usize> ArtNodeTrait<K, V, P> for ArtNode256<K, V, P> {
+    fn add_child(&mut self, child: ArtNode<K, V, P>, byte: u8) {
         self.n.num_children += 1;
-        self.children[byte as usize] = child;
+        self.children[byte as usize] = ChildPtr::new(child);
+        self.occupied.set(byte);
     }
  
-    fn clean_child(&mut self, _byte: u8) -> bool {
+    fn clean_child(&mut self, byte: u8, config: &ArtConfig) -> bool {
+        self.occupied.clear(byte);
         self.n.num_children -= 1;
-        self.n.num_children <= 40 
+        self.n.num_children <= config.shrink_node256
     } 
 
     fn is_full(&self) -> bool {
         self.n.num_children >= 256
     }
 
-    fn to_art_node(self: Box<Self>) -> ArtNode<K,V> {
+    fn to_art_node(self: Box<Self>) -> ArtNode<K, V, P> {
         ArtNode::Inner256(self)
     }
 
-    fn shrink(mut self) -> ArtNode<K,V> {
-        // TODO: several lines here basically same for all the nodes
-        //       try to dedupe somehow.
-        //
-        let mut new_node = Box::new(ArtNode48::new());
-        new_node.n.partial_len = self.n.partial_len;
+    fn shrink(mut self: Box<Self>, _config: &ArtConfig, cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        let node = shrink_into(&mut *self, cache.node48());
+        cache.recycle256(self);
+        node
+    }
 
-        unsafe {
-            ptr::copy_nonoverlapping(
-                self.n.partial.as_ptr(),
-                new_node.n.partial.as_mut_ptr(),
-                self.n.partial.len());
+    fn grow_and_add(self: Box<Self>, _leaf: ArtNode<K, V, P>, _byte: u8, _config: &ArtConfig, _cache: &mut NodeCache<K, V, P>) -> ArtNode<K, V, P> {
+        panic!("Cannot grow ArtNode256");
+    }
+
+    fn drain_into<N: ArtNodeTrait<K, V, P>>(&mut self, to: &mut N) {
+        self.n.copy_prefix_to(to.mut_base());
+        for byte in self.occupied.iter() {
+            to.add_child(self.children[byte as usize].take(), byte);
         }
+        self.occupied = ChildBitmap::new();
+        self.n.num_children = 0;
+    }
 
-        for i in 0..256 {
-            match mem::replace(&mut self.children[i], ArtNode::Empty) {
-                ArtNode::Empty => continue,
-                node => new_node.add_child(node, i as u8),
-            }
+    fn mut_base(&mut self) -> &mut ArtNodeBase<P> {
+        &mut self.n
+    }
+
+    fn base(&self) -> &ArtNodeBase<P> {
+        &self.n
+    }
+
+    fn find_child_mut(&mut self, byte: u8) -> &mut ChildPtr<K, V, P> {
+        &mut self.children[byte as usize]
+    }
+
+    fn find_child(&self, byte: u8) -> Option<&ChildPtr<K, V, P>> {
+        let child = &self.children[byte as usize];
+        if child.is_empty() { None } else { Some(child) }
+    }
+
+    fn has_child(&self, byte: u8) -> bool {
+        !self.children[byte as usize].is_empty()
+    }
+
+    fn first_child(&self) -> &ChildPtr<K, V, P> {
+        match self.occupied.first() {
+            Some(byte) => &self.children[byte as usize],
+            None => panic!("No children"),
         }
+    }
 
-        ArtNode::Inner48(new_node)
+    fn last_child(&self) -> &ChildPtr<K, V, P> {
+        match self.occupied.last() {
+            Some(byte) => &self.children[byte as usize],
+            None => panic!("No children"),
+        }
     }
 
-    fn grow_and_add(self, _leaf: ArtNode<K, V>, _byte: u8) -> ArtNode<K, V> {
-        panic!("Cannot grow ArtNode256");
+    fn take_children(&mut self, out: &mut Vec<ArtNode<K, V, P>>) {
+        for byte in self.occupied.iter() {
+            out.push(self.children[byte as usize].take());
+        }
+        self.occupied = ChildBitmap::new();
+        self.n.num_children = 0;
     }
 
-    fn mut_base(&mut self) -> &mut ArtNodeBase {
-        &mut self.n
+    fn for_each_child<'a, F: FnMut(u8, &'a ChildPtr<K, V, P>)>(&'a self, mut f: F) where K: 'a, V: 'a {
+        for byte in self.occupied.iter() {
+            f(byte, &self.children[byte as usize]);
+        }
     }
+}
 
-    fn base(&self) -> &ArtNodeBase {
-        &self.n
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_sorted(keys: &[u8]) {
+        for w in keys.windows(2) {
+            assert!(w[0] < w[1]);
+        }
     }
 
-    fn find_child_mut(&mut self, byte: u8) -> &mut ArtNode<K, V> {
-        &mut self.children[byte as usize]
+    #[test]
+    fn sorted_node16_keys() {
+        let mut node: ArtNode16<u32, u32, MAX_PREFIX_LEN> = ArtNode16::new();
+        let bytes = [200u8, 3, 77, 15, 255, 0, 42, 128, 9, 64];
+
+        for &b in bytes.iter() {
+            node.add_child(ArtNode::new_leaf(b as u32, b as u32), b);
+            assert_sorted(&node.keys[..node.n.num_children as usize]);
+        }
+
+        for &b in bytes.iter().step_by(2) {
+            node.find_child_mut(b).take();
+            let should_shrink = node.clean_child(b, &ArtConfig::default());
+            assert_sorted(&node.keys[..node.n.num_children as usize]);
+            assert!(!node.has_child(b));
+            assert_eq!(should_shrink, node.n.num_children <= 2);
+        }
+
+        loop {
+            let b = node.keys[node.n.num_children as usize - 1];
+            node.find_child_mut(b).take();
+            if node.clean_child(b, &ArtConfig::default()) {
+                break;
+            }
+        }
+
+        let mut node = match Box::new(node).shrink(&ArtConfig::default(), &mut NodeCache::new(0, SMALL_STRUCT)) {
+            ArtNode::Inner4(node) => node,
+            _ => panic!("Expected ArtNode4"),
+        };
+        assert_sorted(&node.keys[..node.n.num_children as usize]);
+
+        while node.n.num_children > 0 {
+            let b = node.keys[0];
+            node.find_child_mut(b).take();
+            node.clean_child(b, &ArtConfig::default());
+            assert_sorted(&node.keys[..node.n.num_children as usize]);
+        }
+    }
+
+    // Unused key slots keep stale bytes after removes, lookups must not match them.
+    fn check_lookup<N: ArtNodeTrait<u32, u32, MAX_PREFIX_LEN>>(mut node: N, cap: usize) {
+        let bytes: Vec<u8> = (0..cap).map(|i| (i * 37 + 11) as u8).collect();
+        for &b in bytes.iter() {
+            node.add_child(ArtNode::new_leaf(b as u32, b as u32), b);
+        }
+
+        for (i, &b) in bytes.iter().enumerate() {
+            node.find_child_mut(b).take();
+            node.clean_child(b, &ArtConfig::with_intermediate_nodes());
+
+            for byte in 0..256 {
+                let present = bytes[i + 1..].contains(&(byte as u8));
+                assert_eq!(node.has_child(byte as u8), present);
+            }
+        }
+    }
+
+    #[test]
+    fn intermediate_node_lookup() {
+        check_lookup(ArtNode8::new(), 8);
+        check_lookup(ArtNode32::new(), 32);
+    }
+
+    #[test]
+    fn child_ptr_size() {
+        assert_eq!(mem::size_of::<ChildPtr<String, String, MAX_PREFIX_LEN>>(), mem::size_of::<usize>());
+        assert!(mem::size_of::<ArtNode256<String, String, MAX_PREFIX_LEN>>() <= 256 * mem::size_of::<usize>() + 32 + mem::size_of::<ChildBitmap>());
     }
 
-    fn find_child(&self, byte: u8) -> Option<&ArtNode<K, V>> {
-        match &self.children[byte as usize] {
-            &ArtNode::Empty => None,
-            value => Some(value),
+    #[test]
+    fn common_prefix() {
+        let a: Vec<u8> = (0..40).collect();
+        for i in 0..40 {
+            let mut b = a.clone();
+            b[i] = 255;
+            assert_eq!(common_prefix_len(&a, &b), i);
+            assert_eq!(common_prefix_len(&a[..i], &b), i);
         }
+        assert_eq!(common_prefix_len(&a, &a[..17]), 17);
+        assert_eq!(common_prefix_len(&a, &[]), 0);
     }
 
-    fn has_child(&self, byte: u8) -> bool {
-        match self.children[byte as usize] {
-            ArtNode::Empty => false,
-            _ => true,
+    #[test]
+    fn child_bitmap() {
+        let mut bitmap = ChildBitmap::new();
+        assert_eq!((bitmap.first(), bitmap.last(), bitmap.iter().next()), (None, None, None));
+
+        for &byte in [200, 0, 63, 64, 255, 127].iter() {
+            bitmap.set(byte);
+        }
+        bitmap.clear(127);
+        assert_eq!(bitmap.iter().collect::<Vec<_>>(), vec![0, 63, 64, 200, 255]);
+        assert_eq!((bitmap.first(), bitmap.last()), (Some(0), Some(255)));
+
+        let next = [(0, Some(63)), (62, Some(63)), (63, Some(64)), (64, Some(200)), (199, Some(200)), (200, Some(255)), (255, None)];
+        for &(after, byte) in next.iter() {
+            assert_eq!(bitmap.next_child(after), byte);
+        }
+        let prev = [(255, Some(200)), (201, Some(200)), (200, Some(64)), (64, Some(63)), (63, Some(0)), (1, Some(0)), (0, None)];
+        for &(before, byte) in prev.iter() {
+            assert_eq!(bitmap.prev_child(before), byte);
         }
+        assert_eq!(bitmap.iter().rev().collect::<Vec<_>>(), vec![255, 200, 64, 63, 0]);
+
+        // both ends of the iterator meet without returning a byte twice
+        let mut iter = bitmap.iter();
+        assert_eq!((iter.next(), iter.next_back(), iter.next()), (Some(0), Some(255), Some(63)));
+        assert_eq!((iter.next_back(), iter.next(), iter.next_back()), (Some(200), Some(64), None));
+        assert_eq!(iter.next(), None);
+
+        bitmap.clear(0);
+        bitmap.clear(255);
+        assert_eq!((bitmap.next_child(0), bitmap.prev_child(255)), (Some(63), Some(200)));
+        let empty = ChildBitmap::new();
+        assert_eq!((empty.next_child(0), empty.prev_child(255), empty.iter().next_back()), (None, None, None));
+
+        // ArtNode48 moves its last child on removal, `for_each_child` still has to go by key byte
+        let mut node = ArtNode48::<u32, u8, MAX_PREFIX_LEN>::new();
+        for byte in (0..48u8).map(|i| i * 5) {
+            node.add_child(ArtNode::new_leaf(byte as u32, byte), byte);
+        }
+        for byte in (0..48u8).map(|i| i * 5).filter(|byte| byte % 3 == 0) {
+            node.find_child_mut(byte).take();
+            node.clean_child(byte, &ArtConfig::default());
+        }
+
+        let mut bytes = Vec::new();
+        node.for_each_child(|byte, child| {
+            assert_eq!(child.minimum_leaf().value, byte);
+            bytes.push(byte);
+        });
+        assert_eq!(bytes, (0..48u8).map(|i| i * 5).filter(|byte| byte % 3 != 0).collect::<Vec<_>>());
+        assert_eq!(node.first_child().minimum_leaf().value, 5);
+        assert_eq!(node.last_child().minimum_leaf().value, 235);
     }
 }
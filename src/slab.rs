@@ -0,0 +1,188 @@
+use std;
+use std::{mem, ptr};
+use std::alloc::{self, Layout};
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+
+// Slots for small leaves (see `S` of `ArtTree`), handed out from large chunks instead of one allocation
+// per leaf. Saves the allocator overhead of every leaf and keeps leaves inserted together close together.
+//
+// Chunks are aligned to their size, so a slot finds the header of its chunk by masking its address, and
+// a leaf can be freed wherever it is dropped without a reference to the tree (see `LeafPtr`). Chunks with
+// free slots are kept in a list, a chunk is given back to the allocator once its last slot is freed.
+//
+const MIN_CHUNK: usize = 4096;
+const MIN_SLOTS: usize = 32;
+
+struct Chunk {
+    state: *mut SlabState,
+    // Freed slots, linked through their first word. Slots from `bump` on have never been used.
+    free: *mut u8,
+    bump: usize,
+    used: usize,
+    // List of the chunks with free slots.
+    prev: *mut Chunk,
+    next: *mut Chunk,
+}
+
+struct SlabState {
+    partial: *mut Chunk,
+    chunks: usize,
+    used: usize,
+}
+
+pub struct Slab<T> {
+    // Allocated once and only accessed through this pointer, chunks keep a copy of it and the slab moves
+    // with the tree.
+    state: NonNull<SlabState>,
+    marker: PhantomData<T>,
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        let state = Box::new(SlabState { partial: ptr::null_mut(), chunks: 0, used: 0 });
+        Slab {
+            state: unsafe { NonNull::new_unchecked(Box::into_raw(state)) },
+            marker: PhantomData,
+        }
+    }
+
+    // Slots have room for the free list link.
+    #[inline]
+    fn slot_align() -> usize {
+        std::cmp::max(mem::align_of::<T>(), mem::align_of::<*mut u8>())
+    }
+
+    #[inline]
+    fn slot_size() -> usize {
+        let size = std::cmp::max(mem::size_of::<T>(), mem::size_of::<*mut u8>());
+        (size + Self::slot_align() - 1) & !(Self::slot_align() - 1)
+    }
+
+    #[inline]
+    fn header_size() -> usize {
+        (mem::size_of::<Chunk>() + Self::slot_align() - 1) & !(Self::slot_align() - 1)
+    }
+
+    #[inline]
+    fn chunk_size() -> usize {
+        std::cmp::max(MIN_CHUNK, (Self::header_size() + MIN_SLOTS * Self::slot_size()).next_power_of_two())
+    }
+
+    #[inline]
+    fn capacity() -> usize {
+        (Self::chunk_size() - Self::header_size()) / Self::slot_size()
+    }
+
+    fn chunk_layout() -> Layout {
+        Layout::from_size_align(Self::chunk_size(), Self::chunk_size()).unwrap()
+    }
+
+    unsafe fn link(state: &mut SlabState, chunk: *mut Chunk) {
+        (*chunk).prev = ptr::null_mut();
+        (*chunk).next = state.partial;
+        if !state.partial.is_null() {
+            (*state.partial).prev = chunk;
+        }
+        state.partial = chunk;
+    }
+
+    unsafe fn unlink(state: &mut SlabState, chunk: *mut Chunk) {
+        if (*chunk).prev.is_null() {
+            state.partial = (*chunk).next;
+        } else {
+            (*(*chunk).prev).next = (*chunk).next;
+        }
+        if !(*chunk).next.is_null() {
+            (*(*chunk).next).prev = (*chunk).prev;
+        }
+    }
+
+    // Moves `value` into a free slot.
+    pub fn alloc(&mut self, value: T) -> *mut T {
+        let state = self.state.as_ptr();
+
+        unsafe {
+            if (*state).partial.is_null() {
+                let chunk = alloc::alloc(Self::chunk_layout()) as *mut Chunk;
+                if chunk.is_null() {
+                    alloc::handle_alloc_error(Self::chunk_layout());
+                }
+                ptr::write(chunk, Chunk {
+                    state,
+                    free: ptr::null_mut(),
+                    bump: 0,
+                    used: 0,
+                    prev: ptr::null_mut(),
+                    next: ptr::null_mut(),
+                });
+                Self::link(&mut *state, chunk);
+                (*state).chunks += 1;
+            }
+
+            let chunk = (*state).partial;
+            let slot = if (*chunk).free.is_null() {
+                let slot = (chunk as *mut u8).add(Self::header_size() + (*chunk).bump * Self::slot_size());
+                (*chunk).bump += 1;
+                slot
+            } else {
+                let slot = (*chunk).free;
+                (*chunk).free = *(slot as *mut *mut u8);
+                slot
+            };
+
+            (*chunk).used += 1;
+            (*state).used += 1;
+            if (*chunk).used == Self::capacity() {
+                Self::unlink(&mut *state, chunk);
+            }
+
+            ptr::write(slot as *mut T, value);
+            slot as *mut T
+        }
+    }
+
+    // Gives the slot back to the slab it came from. The value has to be moved out or dropped already.
+    pub unsafe fn free(slot: *mut T) {
+        let chunk = (slot as usize & !(Self::chunk_size() - 1)) as *mut Chunk;
+        let state = &mut *(*chunk).state;
+        let was_full = (*chunk).used == Self::capacity();
+
+        *(slot as *mut *mut u8) = (*chunk).free;
+        (*chunk).free = slot as *mut u8;
+        (*chunk).used -= 1;
+        state.used -= 1;
+
+        if (*chunk).used == 0 {
+            if !was_full {
+                Self::unlink(state, chunk);
+            }
+            alloc::dealloc(chunk as *mut u8, Self::chunk_layout());
+            state.chunks -= 1;
+        } else if was_full {
+            Self::link(state, chunk);
+        }
+    }
+
+    // Bytes of the chunks which are not taken by values: free slots and chunk headers.
+    pub fn overhead(&self) -> usize {
+        let state = unsafe { &*self.state.as_ptr() };
+        state.chunks * Self::chunk_size() - state.used * mem::size_of::<T>()
+    }
+}
+
+// The tree drops its leaves before the slab, so there are no chunks left. If there are (leaves leaked by
+// a panic), they still point to the state, which is leaked with them.
+impl<T> Drop for Slab<T> {
+    fn drop(&mut self) {
+        unsafe {
+            if (*self.state.as_ptr()).chunks == 0 {
+                drop(Box::from_raw(self.state.as_ptr()));
+            }
+        }
+    }
+}
+
+// Slots are only allocated and freed through the tree which owns the slab.
+unsafe impl<T: Send> Send for Slab<T> {}
+unsafe impl<T: Sync> Sync for Slab<T> {}
@@ -0,0 +1,219 @@
+use std::mem;
+
+use {ArtKey, ArtTree, CharKey, DurationKey, TimeKey, Id16};
+use nodes::{ArtNode4, ArtNode8, ArtNode16, ArtNode32, ArtNode48, ArtNode256, ArtNodeSpan, ArtNodeBase, ArtNodeTrait, Leaf, NodeRef};
+
+// Bytes used by the tree, per kind of allocation. Leaves are either boxed one by one or stored inline
+// in the leaf slab (see `S` of `ArtTree`), `leaf_slab` is the rest of the slab: free slots and headers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub node4: usize,
+    pub node8: usize,
+    pub node16: usize,
+    pub node32: usize,
+    pub node48: usize,
+    pub node256: usize,
+    pub span: usize,
+    pub boxed_leaves: usize,
+    pub inline_leaves: usize,
+    pub leaf_slab: usize,
+    // Replaced nodes kept for reuse, see `ArtConfig::node_cache`.
+    pub node_cache: usize,
+    // Heap owned by the keys and values themselves, only filled in by `memory_usage_with_heap`.
+    pub key_heap: usize,
+    pub value_heap: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.node4 + self.node8 + self.node16 + self.node32 + self.node48 + self.node256 + self.span + self.boxed_leaves +
+            self.inline_leaves + self.leaf_slab + self.node_cache + self.key_heap + self.value_heap
+    }
+}
+
+// Shape of the tree, see `ArtTree::stats`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArtStats {
+    pub node4: usize,
+    pub node8: usize,
+    pub node16: usize,
+    pub node32: usize,
+    pub node48: usize,
+    pub node256: usize,
+    pub span: usize,
+    pub leaves: usize,
+    // Average number of children of each node type, relative to the node capacity (0.0 to 1.0).
+    pub node4_fill: f64,
+    pub node8_fill: f64,
+    pub node16_fill: f64,
+    pub node32_fill: f64,
+    pub node48_fill: f64,
+    pub node256_fill: f64,
+    pub span_fill: f64,
+    // `leaf_depths[d]` is the number of leaves with `d` inner nodes above them.
+    pub leaf_depths: Vec<usize>,
+    // `partial_lens[l]` is the number of inner nodes with a compressed path of `l` bytes.
+    pub partial_lens: Vec<usize>,
+}
+
+// Heap memory owned by a key or value, not counting `size_of::<Self>()` which is already part of the leaf.
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! no_heap {
+    ($($ty: ty),*) => {
+        $(
+            impl HeapSize for $ty {
+                fn heap_size(&self) -> usize {
+                    0
+                }
+            }
+        )*
+    }
+}
+
+no_heap!(bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, ());
+no_heap!(CharKey, DurationKey, TimeKey, Id16);
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * mem::size_of::<T>() + self.iter().map(|elem| elem.heap_size()).sum::<usize>()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Box<T> {
+    fn heap_size(&self) -> usize {
+        mem::size_of::<T>() + (**self).heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, |elem| elem.heap_size())
+    }
+}
+
+impl<K: ArtKey, V, const P: usize, const S: usize> ArtTree<K, V, P, S> {
+    // Calls `f` for every node and leaf together with its depth, ie. the number of inner nodes above it.
+    // The tree is walked with an explicit stack, same as in `insert`.
+    fn walk<'a, F: FnMut(NodeRef<'a, K, V, P>, usize)>(&'a self, mut f: F) {
+        let mut stack = vec![(&self.root, 0)];
+
+        while let Some((node, depth)) = stack.pop() {
+            let node = node.get();
+
+            match node {
+                NodeRef::Inner4(ptr) => ptr.for_each_child(|_, child| stack.push((child, depth + 1))),
+                NodeRef::Inner8(ptr) => ptr.for_each_child(|_, child| stack.push((child, depth + 1))),
+                NodeRef::Inner16(ptr) => ptr.for_each_child(|_, child| stack.push((child, depth + 1))),
+                NodeRef::Inner32(ptr) => ptr.for_each_child(|_, child| stack.push((child, depth + 1))),
+                NodeRef::Inner48(ptr) => ptr.for_each_child(|_, child| stack.push((child, depth + 1))),
+                NodeRef::Inner256(ptr) => ptr.for_each_child(|_, child| stack.push((child, depth + 1))),
+                NodeRef::Span(ptr) => ptr.for_each_child(|_, child| stack.push((child, depth + 1))),
+                _ => {}
+            }
+
+            f(node, depth);
+        }
+    }
+
+    // Memory usage in a single walk, `leaf_heap` is called for every leaf.
+    fn memory_usage_by<F: FnMut(&Leaf<K, V>, &mut MemoryUsage)>(&self, mut leaf_heap: F) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        let inline = self.cache.has_leaf_slab();
+
+        self.walk(|node, _| match node {
+            NodeRef::Empty => {}
+            NodeRef::Inner4(_) => usage.node4 += mem::size_of::<ArtNode4<K, V, P>>(),
+            NodeRef::Inner8(_) => usage.node8 += mem::size_of::<ArtNode8<K, V, P>>(),
+            NodeRef::Inner16(_) => usage.node16 += mem::size_of::<ArtNode16<K, V, P>>(),
+            NodeRef::Inner32(_) => usage.node32 += mem::size_of::<ArtNode32<K, V, P>>(),
+            NodeRef::Inner48(_) => usage.node48 += mem::size_of::<ArtNode48<K, V, P>>(),
+            NodeRef::Inner256(_) => usage.node256 += mem::size_of::<ArtNode256<K, V, P>>(),
+            NodeRef::Span(_) => usage.span += mem::size_of::<ArtNodeSpan<K, V, P>>(),
+            NodeRef::Leaf(leaf) => {
+                if inline {
+                    usage.inline_leaves += mem::size_of::<Leaf<K, V>>();
+                } else {
+                    usage.boxed_leaves += mem::size_of::<Leaf<K, V>>();
+                }
+                leaf_heap(leaf, &mut usage);
+            }
+        });
+
+        usage.leaf_slab = self.cache.leaf_slab_overhead();
+        usage.node_cache = self.cache.memory_usage();
+        usage
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.memory_usage_by(|_, _| {})
+    }
+
+    // Same as `memory_usage`, and also counts the heap owned by keys and values.
+    pub fn memory_usage_with_heap(&self) -> MemoryUsage
+        where K: HeapSize, V: HeapSize
+    {
+        self.memory_usage_by(|leaf, usage| {
+            usage.key_heap += leaf.key.heap_size();
+            usage.value_heap += leaf.value.heap_size();
+        })
+    }
+
+    pub fn stats(&self) -> ArtStats {
+        let mut stats = ArtStats::default();
+        let mut children = [0; 7];
+
+        fn inc(histogram: &mut Vec<usize>, idx: usize) {
+            if histogram.len() <= idx {
+                histogram.resize(idx + 1, 0);
+            }
+            histogram[idx] += 1;
+        }
+
+        fn inner<const P: usize>(n: &ArtNodeBase<P>, count: &mut usize, children: &mut usize, partial_lens: &mut Vec<usize>) {
+            *count += 1;
+            *children += n.num_children as usize;
+            inc(partial_lens, n.partial_len);
+        }
+
+        self.walk(|node, depth| match node {
+            NodeRef::Empty => {}
+            NodeRef::Inner4(ptr) => inner(ptr.base(), &mut stats.node4, &mut children[0], &mut stats.partial_lens),
+            NodeRef::Inner8(ptr) => inner(ptr.base(), &mut stats.node8, &mut children[1], &mut stats.partial_lens),
+            NodeRef::Inner16(ptr) => inner(ptr.base(), &mut stats.node16, &mut children[2], &mut stats.partial_lens),
+            NodeRef::Inner32(ptr) => inner(ptr.base(), &mut stats.node32, &mut children[3], &mut stats.partial_lens),
+            NodeRef::Inner48(ptr) => inner(ptr.base(), &mut stats.node48, &mut children[4], &mut stats.partial_lens),
+            NodeRef::Inner256(ptr) => inner(ptr.base(), &mut stats.node256, &mut children[5], &mut stats.partial_lens),
+            NodeRef::Span(ptr) => {
+                stats.span += 1;
+                children[6] += ptr.len();
+                inc(&mut stats.partial_lens, ptr.n.partial_len);
+            }
+            NodeRef::Leaf(_) => {
+                stats.leaves += 1;
+                inc(&mut stats.leaf_depths, depth);
+            }
+        });
+
+        let fill = |children: usize, count: usize, capacity: usize| {
+            if count == 0 { 0.0 } else { children as f64 / (count * capacity) as f64 }
+        };
+
+        stats.node4_fill = fill(children[0], stats.node4, 4);
+        stats.node8_fill = fill(children[1], stats.node8, 8);
+        stats.node16_fill = fill(children[2], stats.node16, 16);
+        stats.node32_fill = fill(children[3], stats.node32, 32);
+        stats.node48_fill = fill(children[4], stats.node48, 48);
+        stats.node256_fill = fill(children[5], stats.node256, 256);
+        stats.span_fill = fill(children[6], stats.span, 65536);
+        stats
+    }
+}
@@ -154,3 +154,1184 @@ fn delete_test() {
         }
     }
 }
+
+#[test]
+fn sortable_keys_test() {
+    use art::{CharKey, DurationKey, TimeKey, Id16, ArtKey};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let chars = ['a', 'z', '\u{e9}', '\u{1F600}'];
+    for w in chars.windows(2) {
+        assert!(CharKey::from(w[0]).bytes() < CharKey::from(w[1]).bytes());
+        assert_eq!(CharKey::from(w[0]).get(), w[0]);
+    }
+
+    let durations = [Duration::new(0, 5), Duration::new(1, 0), Duration::new(1, 7), Duration::new(300, 0)];
+    for w in durations.windows(2) {
+        assert!(DurationKey::from(w[0]).bytes() < DurationKey::from(w[1]).bytes());
+        assert_eq!(DurationKey::from(w[0]).get(), w[0]);
+    }
+
+    let times = [
+        UNIX_EPOCH - Duration::new(10, 5),
+        UNIX_EPOCH - Duration::new(10, 0),
+        UNIX_EPOCH - Duration::new(0, 1),
+        UNIX_EPOCH,
+        UNIX_EPOCH + Duration::new(1_500_000_000, 3),
+    ];
+    for w in times.windows(2) {
+        assert!(TimeKey::from(w[0]).bytes() < TimeKey::from(w[1]).bytes());
+        assert_eq!(TimeKey::from(w[0]).get(), w[0]);
+    }
+
+    let mut t = ArtTree::new();
+    for (i, time) in times.iter().enumerate() {
+        t.insert(TimeKey::from(*time), i);
+    }
+    for (i, time) in times.iter().enumerate() {
+        assert_eq!(t.get(&TimeKey::from(*time)), Some(&i));
+    }
+
+    let mut t = ArtTree::new();
+    t.insert(true, 1);
+    t.insert(false, 0);
+    assert_eq!(t.get(&true), Some(&1));
+    assert_eq!(t.get(&false), Some(&0));
+
+    let mut t = ArtTree::new();
+    for i in 0..1000u128 {
+        t.insert(Id16::from(i << 64 | i), i);
+    }
+    for i in 0..1000u128 {
+        assert_eq!(t.get(&Id16::from(i << 64 | i)), Some(&i));
+    }
+}
+
+#[test]
+fn computed_key_test() {
+    use art::ArtKey;
+    use std::borrow::Cow;
+    use std::time::{Duration, SystemTime};
+
+    #[derive(PartialEq)]
+    struct BigEndian(u32);
+
+    impl ArtKey for BigEndian {
+        fn bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Owned(self.0.to_be_bytes().to_vec())
+        }
+    }
+
+    let mut t = ArtTree::new();
+    let n = 5011 as u32;
+
+    for i in 0..n {
+        t.insert(BigEndian(i), i);
+    }
+
+    for i in 0..n {
+        match t.get(&BigEndian(i)) {
+            None => assert!(false),
+            Some(x) => assert_eq!(*x, i),
+        }
+    }
+
+    let now = SystemTime::now();
+    let mut t = ArtTree::new();
+    for i in 0..100 {
+        t.insert(now + Duration::from_millis(i), i);
+    }
+    for i in 0..100 {
+        assert_eq!(t.get(&(now + Duration::from_millis(i))), Some(&i));
+    }
+}
+
+#[test]
+fn key_adapters_test() {
+    use art::{ArtKey, CaseFold, CharKey, Desc, Reversed, TimeKey};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    assert!(Desc(CharKey::from('a')).bytes() > Desc(CharKey::from('b')).bytes());
+
+    let words = ["Apple", "banana", "CHERRY", "Durian"];
+
+    let mut t = ArtTree::new();
+    for (i, w) in words.iter().enumerate() {
+        t.insert(CaseFold(w.to_string()), i);
+    }
+    assert_eq!(t.get(&CaseFold("aPPLE".to_string())), Some(&0));
+    assert_eq!(t.get(&CaseFold("BANANA".to_string())), Some(&1));
+    assert_eq!(t.get(&CaseFold("cherry".to_string())), Some(&2));
+    assert_eq!(t.get(&CaseFold("durian".to_string())), Some(&3));
+
+    t.insert(CaseFold("APPLE".to_string()), 4);
+    assert_eq!(t.get(&CaseFold("apple".to_string())), Some(&4));
+
+    let files = ["/a.txt", "/b.rs", "/c.txt", "/d.md"];
+
+    let mut t = ArtTree::new();
+    for (i, f) in files.iter().enumerate() {
+        t.insert(Reversed(f.to_string()), i);
+    }
+    for (i, f) in files.iter().enumerate() {
+        assert_eq!(t.get(&Reversed(f.to_string())), Some(&i));
+    }
+    assert!(t.get(&Reversed("/e.txt".to_string())).is_none());
+
+    // suffixes are prefixes of the reversed keys
+    let frozen = t.freeze();
+    let txt: Vec<&str> = frozen.scan_prefix(b"txt.").map(|(k, _)| k.0.as_str()).collect();
+    assert_eq!(txt, vec!["/a.txt", "/c.txt"]);
+    assert_eq!(frozen.scan_prefix(b"sr.").map(|(_, &i)| i).collect::<Vec<_>>(), vec![1]);
+    assert_eq!(frozen.scan_prefix(b"gpj.").count(), 0);
+
+    // `Desc` keys are traversed from the largest wrapped key, ie. newest first
+    let times: Vec<SystemTime> = [30u64, 10, 50, 20, 40].iter().map(|&s| UNIX_EPOCH + Duration::from_secs(s)).collect();
+
+    let mut t = ArtTree::new();
+    for time in times.iter() {
+        t.insert(Desc(TimeKey::from(*time)), *time);
+    }
+    assert_eq!(t.first_key_value().map(|(k, _)| k.0.get()), Some(UNIX_EPOCH + Duration::from_secs(50)));
+    assert_eq!(t.last_key_value().map(|(k, _)| k.0.get()), Some(UNIX_EPOCH + Duration::from_secs(10)));
+
+    let mut newest_first = times.clone();
+    newest_first.sort_by(|a, b| b.cmp(a));
+    let frozen = t.freeze();
+    assert_eq!(frozen.iter().map(|(_, &time)| time).collect::<Vec<_>>(), newest_first);
+    assert_eq!(frozen.iter().rev().map(|(k, _)| k.0.get()).next(), Some(UNIX_EPOCH + Duration::from_secs(10)));
+
+    // a prefix of `Desc` keys is inverted as well
+    let mut t = ArtTree::new();
+    for w in ["ab", "ac", "ba"].iter() {
+        t.insert(Desc(format!("{}\0", w)), *w);
+    }
+    let frozen = t.freeze();
+    let a: Vec<&str> = frozen.scan_prefix(&[!b'a']).map(|(_, &w)| w).collect();
+    assert_eq!(a, vec!["ac", "ab"]);
+}
+
+#[test]
+fn node16_lookup_test() {
+    for n in 5..17u32 {
+        let mut t = ArtTree::new();
+
+        for i in 0..n {
+            t.insert(i * 3, i);
+        }
+
+        for i in 0..3 * n {
+            match t.get(&i) {
+                Some(x) => assert_eq!(*x * 3, i),
+                None => assert!(i % 3 != 0 || i >= 3 * n),
+            }
+        }
+    }
+}
+
+#[test]
+fn long_prefix_test() {
+    let prefixes = [
+        "https://example.com/api/v1/users/",
+        "https://example.com/api/v1/groups/",
+        "https://example.com/api/v2/users/",
+        "/home/user/projects/art/src/",
+    ];
+
+    let mut keys = Vec::new();
+    let mut t = ArtTree::new();
+
+    for i in 0..1000 {
+        let key = format!("{}{}/{}\0", prefixes[i % prefixes.len()], i % 7, i);
+        keys.push(key.clone());
+        t.insert(key, i);
+    }
+
+    for i in 0..1000 {
+        assert_eq!(t.get(&keys[i]), Some(&i));
+    }
+
+    assert!(t.get(&"https://example.com/api/v1/users/0/7\0".to_string()).is_none());
+    assert!(t.get(&"https://example.com/api/v3/users/0/0\0".to_string()).is_none());
+    assert!(t.get(&"https://example.com/api/v1/users/0/0".to_string()).is_none());
+
+    for i in (0..1000).step_by(3) {
+        assert_eq!(t.remove(&keys[i]), Some(i));
+    }
+
+    for i in 0..1000 {
+        if i % 3 == 0 {
+            assert!(t.get(&keys[i]).is_none());
+        } else {
+            assert_eq!(t.get(&keys[i]), Some(&i));
+        }
+    }
+}
+
+#[test]
+fn collapse_after_delete_test() {
+    let mut t = ArtTree::new();
+    let mut keys = Vec::new();
+
+    // nested prefixes, so that removing a branch leaves one-child nodes at several levels
+    for a in 0..4 {
+        for b in 0..4 {
+            for c in 0..4 {
+                keys.push(format!("a{}/bbbbbbbbbb{}/cc{}\0", a, b, c));
+            }
+        }
+    }
+
+    for (i, k) in keys.iter().enumerate() {
+        t.insert(k.clone(), i);
+    }
+
+    for (i, k) in keys.iter().enumerate() {
+        if i % 16 != 5 {
+            assert_eq!(t.remove(k), Some(i));
+        }
+    }
+
+    for (i, k) in keys.iter().enumerate() {
+        if i % 16 == 5 {
+            assert_eq!(t.get(k), Some(&i));
+        } else {
+            assert!(t.get(k).is_none());
+        }
+    }
+
+    for (i, k) in keys.iter().enumerate() {
+        t.insert(k.clone(), i + 100);
+    }
+
+    for (i, k) in keys.iter().enumerate() {
+        assert_eq!(t.get(k), Some(&(i + 100)));
+    }
+}
+
+#[test]
+fn config_test() {
+    use art::{ArtConfig, ConfigError};
+
+    let invalid = ArtConfig { shrink_node16: 5, ..ArtConfig::default() };
+    assert_eq!(ArtTree::<u32, u32>::with_config(invalid).err(), Some(ConfigError::Node16Shrink));
+
+    let invalid = ArtConfig { shrink_node48: 17, ..ArtConfig::default() };
+    assert_eq!(ArtTree::<u32, u32>::with_config(invalid).err(), Some(ConfigError::Node48Shrink));
+
+    let invalid = ArtConfig { shrink_node16: 4, shrink_node48: 4, shrink_node256: 40, ..ArtConfig::default() };
+    assert_eq!(ArtTree::<u32, u32>::with_config(invalid).err(), Some(ConfigError::Node48Shrink));
+
+    let invalid = ArtConfig { shrink_node256: 49, ..ArtConfig::default() };
+    assert_eq!(ArtTree::<u32, u32>::with_config(invalid).err(), Some(ConfigError::Node256Shrink));
+
+    let configs = [
+        ArtConfig::default(),
+        ArtConfig { shrink_node16: 2, shrink_node48: 3, shrink_node256: 4, ..ArtConfig::default() },
+        ArtConfig { shrink_node16: 4, shrink_node48: 16, shrink_node256: 48, ..ArtConfig::default() },
+    ];
+
+    for config in configs.iter() {
+        let mut t = ArtTree::with_config(*config).unwrap();
+        assert_eq!(t.config(), config);
+
+        let n = 5011 as u32;
+        for i in 0..n {
+            t.insert(i, i);
+        }
+
+        for i in 0..n {
+            if i % 97 != 0 {
+                assert_eq!(t.remove(&i), Some(i));
+            }
+        }
+
+        for i in 0..n {
+            if i % 97 == 0 {
+                assert_eq!(t.get(&i), Some(&i));
+            } else {
+                assert!(t.get(&i).is_none());
+            }
+        }
+    }
+}
+
+fn mixed_workload(key_range: u32, n: usize) {
+    use std::collections::BTreeMap;
+
+    let mut rng = rand::thread_rng();
+    let mut t = ArtTree::new();
+    let mut m = BTreeMap::new();
+
+    for i in 0..n {
+        let key = rng.gen_range(0, key_range);
+
+        if rng.gen_range(0, 3) == 0 {
+            assert_eq!(t.remove(&key), m.remove(&key));
+        } else {
+            t.insert(key, i);
+            m.insert(key, i);
+        }
+
+        if i % 1000 == 0 {
+            for k in 0..key_range {
+                assert_eq!(t.get(&k), m.get(&k));
+            }
+        }
+    }
+
+    for k in 0..key_range {
+        assert_eq!(t.get(&k), m.get(&k));
+    }
+}
+
+#[test]
+fn mixed_insert_remove_test() {
+    // node sizes hover around the 4/16/48/256 boundaries
+    for &range in [20, 60, 300, 3000, 100_000].iter() {
+        mixed_workload(range, 50_000);
+    }
+}
+
+mod tracked {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Value which keeps count of its live instances, so leaks and double drops show up in the counter.
+    // Padded, so that it does not fit into an inline leaf.
+    pub struct Tracked {
+        live: Rc<Cell<isize>>,
+        pub id: u64,
+        _pad: [u64; 2],
+    }
+
+    impl Tracked {
+        pub fn new(live: &Rc<Cell<isize>>, id: u64) -> Self {
+            live.set(live.get() + 1);
+            Tracked { live: live.clone(), id, _pad: [0; 2] }
+        }
+    }
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.live.set(self.live.get() - 1);
+            assert!(self.live.get() >= 0, "Value dropped twice");
+        }
+    }
+
+    // Same as `Tracked`, but small enough to be stored inline in the leaf.
+    pub struct SmallTracked(pub Rc<Cell<isize>>);
+
+    impl SmallTracked {
+        pub fn new(live: &Rc<Cell<isize>>) -> Self {
+            live.set(live.get() + 1);
+            SmallTracked(live.clone())
+        }
+    }
+
+    impl Drop for SmallTracked {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() - 1);
+            assert!(self.0.get() >= 0, "Value dropped twice");
+        }
+    }
+}
+
+#[test]
+fn drop_test() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use tracked::Tracked;
+
+    let live = Rc::new(Cell::new(0));
+    let mut rng = rand::thread_rng();
+
+    {
+        let mut t = ArtTree::new();
+        let mut keys = Vec::new();
+
+        for i in 0..5000 {
+            let key = rng.gen_range(0, 2000u32);
+            keys.push(key);
+            t.insert(key, Tracked::new(&live, i));
+        }
+
+        // overwritten values are dropped right away
+        assert!(live.get() <= 2000);
+
+        for key in keys.iter().step_by(2) {
+            if let Some(value) = t.remove(key) {
+                assert!(value.id < 5000);
+            }
+        }
+
+        // inner nodes of all sizes are dropped with the tree
+        for i in 0..5000u32 {
+            t.insert(i.wrapping_mul(2654435761), Tracked::new(&live, i as u64));
+        }
+    }
+
+    assert_eq!(live.get(), 0);
+
+    {
+        let mut t = ArtTree::new();
+
+        for i in 0..1000 {
+            t.insert(format!("some/long/common/prefix/{}\0", i), Tracked::new(&live, i));
+        }
+
+        for i in 0..1000 {
+            assert_eq!(t.remove(&format!("some/long/common/prefix/{}\0", i)).map(|v| v.id), Some(i));
+        }
+
+        assert_eq!(live.get(), 0);
+    }
+
+    assert_eq!(live.get(), 0);
+}
+
+#[test]
+fn inline_leaf_test() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use tracked::{SmallTracked, Tracked};
+
+    let live = Rc::new(Cell::new(0));
+
+    {
+        let mut t = ArtTree::new();
+
+        for i in 0..1000u64 {
+            t.insert(i, SmallTracked::new(&live));
+        }
+        assert_eq!(live.get(), 1000);
+
+        // overwrite
+        for i in 0..100u64 {
+            t.insert(i, SmallTracked::new(&live));
+        }
+        assert_eq!(live.get(), 1000);
+
+        for i in 0..500u64 {
+            let value = t.remove(&i);
+            assert!(value.is_some());
+            assert_eq!(live.get(), 1000 - i as isize);
+        }
+        assert_eq!(live.get(), 500);
+
+        for i in 500..1000u64 {
+            assert_eq!(Rc::strong_count(&t.get(&i).unwrap().0), 501);
+        }
+    }
+
+    assert_eq!(live.get(), 0);
+
+    // aligned values stored inline
+    let mut t = ArtTree::new();
+    for i in 0..1000u32 {
+        t.insert(i, i as f64);
+    }
+    for i in 0..1000u32 {
+        assert_eq!(t.get(&i), Some(&(i as f64)));
+    }
+
+    // `Tracked` is inline with a larger `S`, `SmallTracked` boxed with `S` = 0
+    {
+        let mut t = ArtTree::<u64, Tracked, 6, 32>::default();
+        let mut boxed = ArtTree::<u64, SmallTracked, 6, 0>::default();
+
+        for i in 0..1000u64 {
+            t.insert(i, Tracked::new(&live, i));
+            boxed.insert(i, SmallTracked::new(&live));
+        }
+        assert!(t.memory_usage().leaf_slab > 0);
+        assert_eq!(boxed.memory_usage().leaf_slab, 0);
+
+        for i in (0..1000u64).step_by(3) {
+            assert_eq!(t.remove(&i).map(|v| v.id), Some(i));
+            assert!(boxed.remove(&i).is_some());
+        }
+        assert_eq!(live.get(), 2 * 666);
+    }
+
+    assert_eq!(live.get(), 0);
+}
+
+#[test]
+fn node_cache_test() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use art::ArtConfig;
+    use tracked::Tracked;
+
+    let live = Rc::new(Cell::new(0));
+
+    for &cap in [0, 1, 64].iter() {
+        let mut t = ArtTree::with_config(ArtConfig { node_cache: cap, ..ArtConfig::default() }).unwrap();
+
+        // nodes grow and shrink over and over around the 4/16/48/256 boundaries
+        for round in 0..20u32 {
+            let n = [3u32, 5, 17, 49, 255][round as usize % 5];
+            for k in 0..n * 256 {
+                t.insert(k, Tracked::new(&live, k as u64));
+            }
+            for k in 0..n * 256 {
+                if k % 256 >= n / 3 {
+                    assert_eq!(t.remove(&k).map(|v| v.id), Some(k as u64));
+                }
+            }
+            for k in 0..n * 256 {
+                assert_eq!(t.get(&k).is_some(), k % 256 < n / 3);
+            }
+        }
+
+        drop(t);
+        assert_eq!(live.get(), 0);
+    }
+}
+
+fn url_workload<const P: usize, const S: usize>(mut t: ArtTree<String, usize, P, S>) {
+    let url = |i: usize| format!("https://example.com/some/long/path/{}/{}\0", i % 13, i);
+
+    for i in 0..1000 {
+        t.insert(url(i), i);
+    }
+
+    for i in 0..1000 {
+        assert_eq!(t.get(&url(i)), Some(&i));
+    }
+    assert!(t.get(&"https://example.com/some/long/path/1/0\0".to_string()).is_none());
+
+    for i in (0..1000).step_by(2) {
+        assert_eq!(t.remove(&url(i)), Some(i));
+    }
+
+    for i in 0..1000 {
+        if i % 2 == 0 {
+            assert!(t.get(&url(i)).is_none());
+        } else {
+            assert_eq!(t.get(&url(i)), Some(&i));
+        }
+    }
+}
+
+#[test]
+fn layout_test() {
+    use art::{ArtConfig, Id16};
+
+    url_workload(ArtTree::new());
+    url_workload(ArtTree::<String, usize, 0>::default());
+    url_workload(ArtTree::<String, usize, 48>::default());
+    url_workload(ArtTree::<String, usize, 64, 32>::with_layout(ArtConfig::default()).unwrap());
+
+    // 16-byte ids stored inline
+    let mut t = ArtTree::<Id16, u64, 6, 16>::default();
+    for i in 0..1000u64 {
+        t.insert(Id16::from((i as u128) << 64 | 7), i);
+    }
+    for i in 0..1000u64 {
+        assert_eq!(t.get(&Id16::from((i as u128) << 64 | 7)), Some(&i));
+    }
+}
+
+#[test]
+fn arena_test() {
+    use std::cell::Cell;
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+    use art::{ArenaArtTree, ArtConfig, ConfigError};
+    use tracked::Tracked;
+
+    let mut rng = rand::thread_rng();
+
+    // no ArtNode8/ArtNode32 or span nodes in the arena, their configs used to shrink nodes out of bounds
+    let config = ArtConfig::with_intermediate_nodes();
+    assert_eq!(ArenaArtTree::<u32, u32>::with_config(config).err(), Some(ConfigError::IntermediateNodesUnsupported));
+    let config = ArtConfig { span_nodes: true, ..ArtConfig::default() };
+    assert_eq!(ArenaArtTree::<u32, u32>::with_config(config).err(), Some(ConfigError::SpanNodesUnsupported));
+
+    // highest thresholds which the arena node types allow
+    let config = ArtConfig { shrink_node16: 4, shrink_node48: 16, shrink_node256: 48, ..ArtConfig::default() };
+    let mut t = ArenaArtTree::with_config(config).unwrap();
+    for round in 0..10u32 {
+        let n = [3u32, 5, 17, 49, 255][round as usize % 5];
+        for k in 0..n * 256 {
+            t.insert(k, k);
+        }
+        for k in (0..n * 256).filter(|k| k % 256 >= n / 3) {
+            assert_eq!(t.remove(&k), Some(k));
+        }
+        for k in 0..n * 256 {
+            assert_eq!(t.get(&k).is_some(), k % 256 < n / 3);
+        }
+    }
+
+    for &range in [20u32, 60, 300, 3000, 100_000].iter() {
+        let mut t = ArenaArtTree::new();
+        let mut m = BTreeMap::new();
+
+        for i in 0..50_000 {
+            let key = rng.gen_range(0, range);
+
+            if rng.gen_range(0, 3) == 0 {
+                assert_eq!(t.remove(&key), m.remove(&key));
+            } else {
+                t.insert(key, i);
+                m.insert(key, i);
+            }
+        }
+
+        assert_eq!(t.len(), m.len());
+        assert_eq!(t.is_empty(), m.is_empty());
+        for k in 0..range {
+            assert_eq!(t.get(&k), m.get(&k));
+        }
+    }
+
+    // prefixes longer than the stored part
+    let url = |i: usize| format!("https://example.com/some/long/path/{}/{}\0", i % 13, i);
+    let mut t = ArenaArtTree::<String, usize, 4>::with_layout(ArtConfig::default()).unwrap();
+    for i in 0..1000 {
+        t.insert(url(i), i);
+    }
+    for i in (0..1000).step_by(2) {
+        assert_eq!(t.remove(&url(i)), Some(i));
+    }
+    for i in 0..1000 {
+        assert_eq!(t.get(&url(i)), if i % 2 == 0 { None } else { Some(&i) });
+    }
+
+    // keys without `PartialEq` are matched by their bytes
+    struct NoEq(u32);
+
+    impl art::ArtKey for NoEq {
+        fn bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+            std::borrow::Cow::Owned(self.0.to_be_bytes().to_vec())
+        }
+    }
+
+    let mut t = ArenaArtTree::new();
+    for i in 0..100 {
+        t.insert(NoEq(i), i);
+    }
+    t.insert(NoEq(7), 107);
+    assert_eq!(t.len(), 100);
+    assert_eq!(t.get(&NoEq(7)), Some(&107));
+    assert_eq!(t.remove(&NoEq(8)), Some(8));
+    assert_eq!(t.get(&NoEq(100)), None);
+
+    let live = Rc::new(Cell::new(0));
+    {
+        let mut t = ArenaArtTree::new();
+        for i in 0..5000 {
+            t.insert(rng.gen_range(0, 2000u32), Tracked::new(&live, i));
+        }
+        assert!(live.get() <= 2000);
+
+        for k in 0..1000 {
+            t.remove(&k);
+        }
+    }
+    assert_eq!(live.get(), 0);
+}
+
+#[test]
+fn deep_tree_test() {
+    // every key branches off the previous one, so the tree is as deep as the longest key
+    let key = |i: usize| {
+        let mut key = vec![b'a'; i];
+        key.push(b'b');
+        key
+    };
+
+    let mut t = ArtTree::new();
+    for i in 0..5000 {
+        t.insert(key(i), i);
+    }
+    for i in 0..5000 {
+        assert_eq!(t.get(&key(i)), Some(&i));
+    }
+    for i in (0..5000).rev().step_by(2) {
+        assert_eq!(t.remove(&key(i)), Some(i));
+    }
+    for i in 0..5000 {
+        assert_eq!(t.get(&key(i)), if i % 2 == 1 { None } else { Some(&i) });
+    }
+}
+
+#[test]
+fn memory_usage_test() {
+    use std::mem::size_of;
+    use art::{ArtConfig, MemoryUsage};
+
+    let mut t = ArtTree::with_config(ArtConfig { node_cache: 0, ..ArtConfig::default() }).unwrap();
+    assert_eq!(t.memory_usage(), MemoryUsage::default());
+
+    for i in 0..10_000u64 {
+        t.insert(i, i);
+    }
+
+    let usage = t.memory_usage();
+    assert_eq!((usage.inline_leaves, usage.boxed_leaves), (10_000 * size_of::<(u64, u64)>(), 0));
+    assert!(usage.node256 > 0);
+    assert!(usage.node48 > 0);
+    assert_eq!(usage.key_heap + usage.value_heap + usage.node_cache, 0);
+    // inline leaves fill their slab chunks
+    assert!(usage.leaf_slab > 0 && usage.leaf_slab < usage.inline_leaves / 10);
+    assert_eq!(usage.total(), usage.node4 + usage.node16 + usage.node48 + usage.node256 + usage.inline_leaves + usage.leaf_slab);
+
+    for i in 0..10_000u64 {
+        t.remove(&i);
+    }
+    assert_eq!(t.memory_usage(), MemoryUsage::default());
+
+    // heap owned by keys and values
+    let mut t = ArtTree::new();
+    let mut key_heap = 0;
+    for i in 0..1000 {
+        let key = format!("key/{}\0", i);
+        key_heap += key.capacity();
+        t.insert(key, vec![0u32; i % 10]);
+    }
+
+    let usage = t.memory_usage_with_heap();
+    // keys too large for inline leaves
+    assert_eq!((usage.boxed_leaves, usage.inline_leaves), (1000 * size_of::<(String, Vec<u32>)>(), 0));
+    assert_eq!(usage.leaf_slab, 0);
+    assert_eq!(usage.key_heap, key_heap);
+    assert_eq!(usage.value_heap, (0..1000).map(|i| (i % 10) * size_of::<u32>()).sum::<usize>());
+}
+
+#[test]
+fn stats_test() {
+    let mut t = ArtTree::new();
+    assert_eq!(t.stats().leaves, 0);
+
+    // first key byte (little endian) is spread over 256 values, second one over 3 or 4
+    for i in 0..1000u32 {
+        t.insert(i, i);
+    }
+
+    let stats = t.stats();
+    assert_eq!((stats.node4, stats.node16, stats.node48, stats.node256), (256, 0, 0, 1));
+    assert_eq!(stats.leaves, 1000);
+    assert_eq!(stats.leaf_depths, vec![0, 0, 1000]);
+    assert_eq!(stats.partial_lens, vec![257]);
+    assert_eq!(stats.node256_fill, 1.0);
+    assert_eq!(stats.node4_fill, 1000.0 / 1024.0);
+    assert_eq!(stats.node16_fill, 0.0);
+
+    // long common prefixes
+    let mut t = ArtTree::new();
+    t.insert("common/prefix/a\0".to_string(), 0);
+    t.insert("common/prefix/b\0".to_string(), 1);
+
+    let stats = t.stats();
+    assert_eq!(stats.node4, 1);
+    assert_eq!(stats.leaf_depths, vec![0, 2]);
+    assert_eq!(stats.partial_lens.len(), 15);
+    assert_eq!(stats.partial_lens[14], 1);
+}
+
+#[test]
+fn intermediate_nodes_test() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use art::{ArtConfig, ConfigError};
+    use tracked::Tracked;
+
+    // default thresholds do not leave room for ArtNode8
+    let invalid = ArtConfig { intermediate_nodes: true, ..ArtConfig::default() };
+    assert_eq!(ArtTree::<u32, u32>::with_config(invalid).err(), Some(ConfigError::Node16Shrink));
+
+    let invalid = ArtConfig { shrink_node32: 16, shrink_node48: 16, ..ArtConfig::with_intermediate_nodes() };
+    assert_eq!(ArtTree::<u32, u32>::with_config(invalid).err(), Some(ConfigError::Node48Shrink));
+
+    let live = Rc::new(Cell::new(0));
+    let mut t = ArtTree::with_config(ArtConfig::with_intermediate_nodes()).unwrap();
+
+    // nodes grow and shrink over and over around the 4/8/16/32/48/256 boundaries
+    for round in 0..30u32 {
+        let n = [3u32, 7, 9, 17, 31, 33, 49, 255][round as usize % 8];
+        for k in 0..n * 256 {
+            t.insert(k, Tracked::new(&live, k as u64));
+        }
+        for k in 0..n * 256 {
+            if k % 256 >= n / 3 {
+                assert_eq!(t.remove(&k).map(|v| v.id), Some(k as u64));
+            }
+        }
+        for k in 0..n * 256 {
+            assert_eq!(t.get(&k).is_some(), k % 256 < n / 3);
+        }
+    }
+
+    drop(t);
+    assert_eq!(live.get(), 0);
+
+    // every key byte (little endian) is spread over 6 values, ArtNode8 instead of ArtNode16
+    let keys: Vec<u32> = (0..6 * 6 * 6).map(|i| (i % 6) | ((i / 6 % 6) << 8) | ((i / 36) << 16)).collect();
+    let mut small = ArtTree::with_config(ArtConfig::with_intermediate_nodes()).unwrap();
+    let mut large = ArtTree::new();
+    for &k in keys.iter() {
+        small.insert(k, k);
+        large.insert(k, k);
+    }
+
+    let (stats, large_stats) = (small.stats(), large.stats());
+    assert_eq!((stats.node8, stats.node16), (1 + 6 + 36, 0));
+    assert_eq!((large_stats.node8, large_stats.node16), (0, 1 + 6 + 36));
+    assert_eq!(stats.node8_fill, 6.0 / 8.0);
+    assert!(small.memory_usage().total() < large.memory_usage().total());
+
+    for &k in keys.iter() {
+        assert_eq!(small.get(&k), Some(&k));
+    }
+
+    // 20 values per byte, ArtNode32 instead of ArtNode48
+    let mut t = ArtTree::with_config(ArtConfig::with_intermediate_nodes()).unwrap();
+    let mut large = ArtTree::new();
+    for i in 0..400u32 {
+        t.insert((i % 20) | ((i / 20) << 8), i);
+        large.insert((i % 20) | ((i / 20) << 8), i);
+    }
+    let stats = t.stats();
+    assert_eq!((stats.node32, stats.node48), (21, 0));
+    assert_eq!(large.stats().node48, 21);
+
+    let (usage, large_usage) = (t.memory_usage(), large.memory_usage());
+    assert!(usage.total() - usage.inline_leaves - usage.node_cache < large_usage.total() - large_usage.inline_leaves - large_usage.node_cache);
+}
+
+#[test]
+fn span_nodes_test() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use art::{ArtConfig, Id16};
+    use tracked::Tracked;
+
+    let live = Rc::new(Cell::new(0));
+    let config = ArtConfig { span_nodes: true, ..ArtConfig::default() };
+    let mut t = ArtTree::with_config(config).unwrap();
+
+    // sequential ids, the first two key bytes (little endian) are dense
+    for i in 0..100_000u64 {
+        t.insert(i, Tracked::new(&live, i));
+    }
+
+    let stats = t.stats();
+    assert_eq!((stats.span, stats.node256), (1, 0));
+    assert_eq!(stats.leaf_depths.len(), 3);
+
+    // span node has all 65536 child slots, which is no more than the ArtNode256s it replaced
+    let mut plain = ArtTree::new();
+    for i in 0..100_000u64 {
+        plain.insert(i, i);
+    }
+    let usage = t.memory_usage();
+    assert!(usage.span >= 65536 * std::mem::size_of::<usize>());
+    assert_eq!(plain.stats().node256, 257);
+    assert!(usage.span <= plain.memory_usage().node256);
+    for i in 0..100_000u64 {
+        assert_eq!(t.get(&i).map(|v| v.id), Some(i));
+    }
+
+    // span node is split back once one of the first bytes gets sparse
+    let sparse = |i: u64| i & 0xff == 7 && (i >> 8) & 0xff >= 40;
+    for i in (0..100_000u64).filter(|&i| sparse(i)) {
+        assert_eq!(t.remove(&i).map(|v| v.id), Some(i));
+    }
+
+    let stats = t.stats();
+    assert_eq!((stats.span, stats.node256, stats.node48), (0, 256, 1));
+    for i in 0..100_000u64 {
+        assert_eq!(t.get(&i).map(|v| v.id), if sparse(i) { None } else { Some(i) });
+    }
+
+    for i in (0..100_000u64).filter(|&i| sparse(i)) {
+        t.insert(i, Tracked::new(&live, i));
+    }
+    assert_eq!(t.stats().span, 1);
+
+    drop(t);
+    assert_eq!(live.get(), 0);
+
+    // big endian ids, the span node has a long prefix which an insert breaks
+    let mut t = ArtTree::with_config(config).unwrap();
+    for i in 0..65536u32 {
+        t.insert(Id16::from(i as u128), i);
+    }
+    assert_eq!(t.stats().span, 1);
+    assert_eq!(t.stats().partial_lens[14], 1);
+
+    t.insert(Id16::from(1u128 << 100), 65536);
+    assert_eq!(t.stats().span, 0);
+    assert_eq!(t.get(&Id16::from(1u128 << 100)), Some(&65536));
+    for i in 0..65536u32 {
+        assert_eq!(t.get(&Id16::from(i as u128)), Some(&i));
+        assert_eq!(t.remove(&Id16::from(i as u128)), Some(i));
+    }
+    assert_eq!(t.stats().leaves, 1);
+}
+
+#[test]
+fn frozen_test() {
+    use std::collections::BTreeMap;
+    use art::ArtConfig;
+
+    let mut rng = rand::thread_rng();
+
+    // long shared prefixes, dense and sparse nodes
+    let mut t = ArtTree::new();
+    let mut keys = BTreeMap::new();
+    for i in 0..20_000 {
+        let mut key = b"a long shared prefix/".to_vec();
+        let len = rng.gen_range(1, 4);
+        key.extend((0..len).map(|_| rng.gen_range(0, 200) as u8));
+        key.extend(b"/key");
+        t.insert(key.clone(), i);
+        keys.insert(key, i);
+    }
+
+    let frozen = t.freeze();
+    assert_eq!(frozen.len(), keys.len());
+    assert!(frozen.iter().map(|(k, v)| (k.clone(), *v)).eq(keys.iter().map(|(k, v)| (k.clone(), *v))));
+    for (key, value) in keys.iter() {
+        assert_eq!(frozen.get(key), Some(value));
+    }
+    assert_eq!(frozen.get(&b"a long shared prefix/".to_vec()), None);
+    assert_eq!(frozen.get(&b"a long shared prefix/\x01\x02\x03/kez".to_vec()), None);
+    assert_eq!(frozen.get(&b"a short prefix".to_vec()), None);
+
+    for prefix in [&b""[..], b"a long", b"a long shared prefix/", b"a long shared prefix/\x07", b"a long shared prefix/\x07\x08",
+                   b"a long shared prefix/\x07/key", b"a lone", b"b"].iter() {
+        let expected: Vec<_> = keys.iter().filter(|&(k, _)| k.starts_with(prefix)).map(|(_, v)| *v).collect();
+        assert_eq!(frozen.scan_prefix(prefix).map(|(_, v)| *v).collect::<Vec<_>>(), expected);
+    }
+    assert_eq!(frozen.scan_prefix(b"a long").rev().next().map(|(k, _)| k), keys.keys().next_back());
+
+    // every node type
+    for &config in [ArtConfig::default(), ArtConfig::with_intermediate_nodes(), ArtConfig { span_nodes: true, ..ArtConfig::default() }].iter() {
+        let mut t = ArtTree::with_config(config).unwrap();
+        let mut keys = Vec::new();
+        for i in 0..70_000u64 {
+            let key = if i % 3 == 0 { rng.gen::<u64>() } else { i };
+            t.insert(key, i);
+            keys.push(key);
+        }
+        keys.sort_by_key(|k| k.to_le_bytes());
+        keys.dedup();
+
+        let frozen = t.freeze();
+        assert!(frozen.iter().map(|(k, _)| *k).eq(keys.iter().cloned()));
+        for key in keys.iter() {
+            assert!(frozen.get(key).is_some());
+        }
+        for _ in 0..10_000 {
+            let key = rng.gen::<u64>();
+            assert_eq!(frozen.get(&key).is_some(), keys.binary_search_by_key(&key.to_le_bytes(), |k| k.to_le_bytes()).is_ok());
+        }
+        assert_eq!(frozen.scan_prefix(&[1, 2]).count(), keys.iter().filter(|k| k.to_le_bytes()[..2] == [1, 2]).count());
+    }
+
+    // empty and single leaf trees
+    let t: ArtTree<u64, u64> = ArtTree::new();
+    let frozen = t.freeze();
+    assert!(frozen.is_empty());
+    assert_eq!(frozen.get(&1), None);
+    assert_eq!(frozen.scan_prefix(&[]).count(), 0);
+
+    // overwritten keys are counted once
+    let mut t = ArtTree::new();
+    t.insert(1u64, 0);
+    t.insert(1u64, 1);
+    assert_eq!(t.len(), 1);
+    let frozen = t.freeze();
+    assert_eq!(frozen.len(), 1);
+    assert_eq!(frozen.get(&1), Some(&1));
+    assert_eq!(frozen.get(&2), None);
+    assert_eq!(frozen.scan_prefix(&[1]).count(), 1);
+    assert_eq!(frozen.scan_prefix(&[2]).count(), 0);
+
+    // keys without `PartialEq` are matched by their bytes
+    struct NoEq(u32);
+
+    impl art::ArtKey for NoEq {
+        fn bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+            std::borrow::Cow::Owned(self.0.to_be_bytes().to_vec())
+        }
+    }
+
+    let mut t = ArtTree::new();
+    for i in 0..100 {
+        t.insert(NoEq(i), i);
+    }
+    assert_eq!(t.get(&NoEq(7)), Some(&7));
+    let frozen = t.freeze();
+    assert_eq!(frozen.get(&NoEq(7)), Some(&7));
+    assert_eq!(frozen.get(&NoEq(100)), None);
+}
+
+#[test]
+fn get_many_test() {
+    use art::ArtConfig;
+
+    let mut rng = rand::thread_rng();
+
+    for &config in [ArtConfig::default(), ArtConfig { span_nodes: true, ..ArtConfig::default() }].iter() {
+        let mut t = ArtTree::with_config(config).unwrap();
+        for i in 0..70_000u64 {
+            let key = if i % 2 == 0 { rng.gen::<u64>() } else { i };
+            t.insert(key, key);
+        }
+
+        // hits and misses at every depth, more than one group
+        let keys: Vec<u64> = (0..5_000u64).map(|i| match i % 4 {
+            0 => i | 1,
+            1 => rng.gen::<u64>(),
+            2 => i << 32,
+            _ => 70_000 + i,
+        }).collect();
+
+        let found = t.get_many(&keys);
+        assert_eq!(found.len(), keys.len());
+        for (key, value) in keys.iter().zip(found) {
+            assert_eq!(value, t.get(key));
+        }
+        assert_eq!(t.get_many(&keys[..3]), vec![Some(&1), None, None]);
+    }
+
+    let t: ArtTree<String, u32> = ArtTree::new();
+    assert!(t.get_many(&[]).is_empty());
+    assert_eq!(t.get_many(&["a".to_string()]), vec![None]);
+}
+
+#[test]
+fn first_last_test() {
+    use std::collections::BTreeMap;
+    use art::ArtConfig;
+
+    let mut rng = rand::thread_rng();
+
+    for &config in [ArtConfig::default(), ArtConfig::with_intermediate_nodes(), ArtConfig { span_nodes: true, ..ArtConfig::default() }].iter() {
+        let mut t = ArtTree::with_config(config).unwrap();
+        let mut keys = BTreeMap::new();
+        assert_eq!(t.first_key_value(), None);
+        assert_eq!(t.last_key_value(), None);
+
+        // byte order of the little endian keys
+        for i in 0..100_000u64 {
+            let key = if i % 4 == 0 { rng.gen::<u64>() } else { i };
+            t.insert(key, i);
+            keys.insert(key.to_le_bytes(), key);
+        }
+
+        // removing the extremes empties and shrinks ArtNode256/ArtNode48 from both ends
+        for _ in 0..20_000 {
+            let (&first, &min) = keys.iter().next().unwrap();
+            let (&last, &max) = keys.iter().next_back().unwrap();
+            assert_eq!(t.first_key_value().map(|(k, _)| *k), Some(min));
+            assert_eq!(t.last_key_value().map(|(k, _)| *k), Some(max));
+
+            assert!(t.remove(&min).is_some());
+            assert!(t.remove(&max).is_some());
+            keys.remove(&first);
+            keys.remove(&last);
+        }
+    }
+}
+
+#[test]
+fn compact_test() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use art::ArtConfig;
+    use tracked::Tracked;
+
+    let live = Rc::new(Cell::new(0));
+    let configs = [ArtConfig::default(), ArtConfig::with_intermediate_nodes(), ArtConfig { span_nodes: true, ..ArtConfig::default() }];
+
+    for &config in configs.iter() {
+        // second key byte (little endian) is down to 3 values, the nodes branching on it stop shrinking
+        // above ArtNode4 because of the shrink thresholds
+        let mut t = ArtTree::with_config(config).unwrap();
+        for i in 0..100_000u64 {
+            t.insert(i, Tracked::new(&live, i));
+        }
+        let keep = |i: u64| (i >> 8) & 0xff < 3;
+        for i in (0..100_000u64).filter(|&i| !keep(i)) {
+            assert_eq!(t.remove(&i).map(|v| v.id), Some(i));
+        }
+
+        // same shape as a tree which never had the removed keys
+        let mut fresh = ArtTree::with_config(config).unwrap();
+        for i in (0..100_000u64).filter(|&i| keep(i)) {
+            fresh.insert(i, i);
+        }
+        assert!(t.stats() != fresh.stats());
+
+        let before = t.memory_usage().total();
+        t.compact();
+        assert_eq!(t.stats(), fresh.stats());
+        let stats = t.stats();
+        assert_eq!((stats.node4, stats.node8, stats.node16), (256 + 256 * 3, 0, 0));
+        assert_eq!(t.memory_usage().node_cache, 0);
+        assert!(t.memory_usage().total() < before);
+
+        for i in 0..100_000u64 {
+            assert_eq!(t.get(&i).map(|v| v.id), if keep(i) { Some(i) } else { None });
+        }
+
+        // the compacted tree grows and shrinks as usual
+        for i in 0..100_000u64 {
+            if keep(i) {
+                assert_eq!(t.remove(&i).map(|v| v.id), Some(i));
+            } else {
+                t.insert(i, Tracked::new(&live, i));
+            }
+        }
+        for i in 0..100_000u64 {
+            assert_eq!(t.get(&i).map(|v| v.id), if keep(i) { None } else { Some(i) });
+        }
+        t.compact();
+        for i in (0..100_000u64).step_by(7) {
+            assert_eq!(t.get(&i).map(|v| v.id), if keep(i) { None } else { Some(i) });
+        }
+    }
+    assert_eq!(live.get(), 0);
+
+    // long prefixes, past the stored part of `partial`
+    let mut rng = rand::thread_rng();
+    let key = |i: u32| format!("users/{:08}/profile/{}\0", i % 1000, i / 1000);
+    let mut t = ArtTree::new();
+    for i in 0..20_000u32 {
+        t.insert(key(i), i);
+    }
+    let mut kept = Vec::new();
+    for i in 0..20_000u32 {
+        if rng.gen::<u32>() % 10 == 0 {
+            kept.push(i);
+        } else {
+            assert_eq!(t.remove(&key(i)), Some(i));
+        }
+    }
+
+    t.compact();
+    let mut fresh = ArtTree::new();
+    for &i in kept.iter() {
+        fresh.insert(key(i), i);
+    }
+    assert_eq!(t.stats(), fresh.stats());
+    for &i in kept.iter() {
+        assert_eq!(t.get(&key(i)), Some(&i));
+    }
+    assert_eq!(t.get(&key(20_001)), None);
+
+    // root leaf and empty tree
+    let mut t = ArtTree::new();
+    t.compact();
+    t.insert(1u64, 1);
+    t.compact();
+    assert_eq!(t.get(&1), Some(&1));
+    assert_eq!(t.remove(&1), Some(1));
+}